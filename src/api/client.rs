@@ -1,25 +1,32 @@
-use crate::api::models::{ImageItem, PageResponse};
-use crate::cli::Args;
-use crate::config::FerrumenaConfig;
+use crate::api::models::{ApiSearchResponse, ImageItem, PageResponse};
+use crate::api::throttle::{AdaptiveLimiter, HostLimiter, RateChange, retry_after};
+use crate::config::{FerrumenaConfig, SearchBackend};
 use crate::error::{FerrumenaError, Result};
-use crate::utils::{extract_total_from_first_page, parse_representations, pick_view_url};
-use governor::{
-    Quota, RateLimiter,
-    clock::DefaultClock,
-    state::{InMemoryState, NotKeyed},
+use crate::events::{DownloadEvent, DownloadObserver, Observers};
+use crate::retry::RetryPolicy;
+use crate::search::{PaginationMode, SearchRequest};
+use crate::utils::{
+    extract_total_from_first_page, mime_from_format, parse_attr, parse_representations,
+    parse_tag_ids, parse_timestamp, pick_view_url, split_tag_list,
 };
-use reqwest::{Client, header};
+use futures::{Stream, stream};
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header};
 use scraper::Selector;
+use std::collections::VecDeque;
 use std::num::NonZeroU32;
 use std::sync::Arc;
-
-// 速度限制器
-type SharedLimiter = Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub struct PhilomenaClient {
-    pub(crate) client: Client,   // reqwest 客户端
-    pub config: FerrumenaConfig, // 内部所有 config
-    limiter: SharedLimiter,
+    pub(crate) client: Client,             // reqwest 客户端
+    pub config: FerrumenaConfig,           // 内部所有 config
+    pub(crate) limiter: AdaptiveLimiter,   // 自适应限速器，所有 Worker 共享暂停状态
+    pub(crate) image_limiter: HostLimiter, // 图片请求限速器，按主机计数
+    pub retry: RetryPolicy,                // 页面与图片共用的重试策略
+    // auto 模式下 JSON API 失败后置位，之后只走 HTML
+    json_unavailable: AtomicBool,
+    // 客户端自身的事件（重试、限流、后端回退）的观察者
+    observers: Observers,
 }
 
 impl PhilomenaClient {
@@ -50,50 +57,295 @@ impl PhilomenaClient {
         // 3. 构造限速器
         let rps = NonZeroU32::new(config.rps)
             .ok_or_else(|| FerrumenaError::Config("RPS (每秒请求数) 必须大于 0".to_string()))?;
-        let limiter = Arc::new(RateLimiter::direct(Quota::per_second(rps)));
+        let limiter = AdaptiveLimiter::new(rps.get());
+        let image_limiter = HostLimiter::new(config.image_rps, config.image_bandwidth);
 
         // 4. 构建 Reqwest Client
         let client = Client::builder().default_headers(headers).build()?;
 
+        // 5. 重试策略
+        let retry = RetryPolicy::from_config(&config);
+
         Ok(Self {
             client,
+            retry,
             config,
             limiter,
+            image_limiter,
+            json_unavailable: AtomicBool::new(false),
+            observers: Observers::default(),
         })
     }
 
+    /// 订阅客户端自身的事件：页面重试、限流降速与恢复、搜索后端回退
+    ///
+    /// 客户端在多个下载器之间共享，这些事件不会发给下载器的观察者；
+    /// 可多次调用添加多个观察者
+    pub fn observer(mut self, observer: impl DownloadObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// 分发客户端事件
+    pub(crate) fn emit(&self, event: DownloadEvent<'_>) {
+        self.observers.emit(event);
+    }
+
+    /// 发送页面或 API 请求，响应交给页面限速器观察后检查状态
+    pub(crate) async fn send_checked(&self, req: RequestBuilder) -> Result<Response> {
+        let resp = req.send().await?;
+        match self.limiter.observe(&resp) {
+            Some(RateChange::Limited { rps, pause }) => {
+                self.emit(DownloadEvent::RateLimited { rps, pause })
+            }
+            Some(RateChange::Recovered { rps }) => self.emit(DownloadEvent::RateRecovered { rps }),
+            None => {}
+        }
+        Self::check_status(resp)
+    }
+
+    /// 发送图片请求，响应只交给主机 `host` 的图片限速器观察，不影响翻页速率
+    pub(crate) async fn send_image(&self, req: RequestBuilder, host: &str) -> Result<Response> {
+        let resp = req.send().await?;
+        match self.image_limiter.observe(host, &resp) {
+            Some(RateChange::Limited { rps, pause }) => {
+                self.emit(DownloadEvent::HostRateLimited { host, rps, pause })
+            }
+            Some(RateChange::Recovered { rps }) => {
+                self.emit(DownloadEvent::HostRateRecovered { host, rps })
+            }
+            None => {}
+        }
+        Self::check_status(resp)
+    }
+
+    /// 把异常响应转换为对应的错误类型
+    ///
+    /// 依次识别 Cloudflare、429、登录、404/410 和其它非 2xx 状态
+    fn check_status(resp: Response) -> Result<Response> {
+        let status = resp.status();
+        let url = resp.url().to_string();
+
+        let cf_challenge = resp
+            .headers()
+            .get("cf-mitigated")
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"challenge"));
+        if cf_challenge {
+            return Err(FerrumenaError::Cloudflare { url });
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(FerrumenaError::RateLimited {
+                retry_after: retry_after(&resp),
+                url,
+            });
+        }
+        // Philomena 会把需要登录的请求重定向到登录页
+        if status == StatusCode::UNAUTHORIZED || resp.url().path().starts_with("/sessions/new") {
+            return Err(FerrumenaError::LoginRequired { url });
+        }
+        if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+            return Err(FerrumenaError::NotFound { url });
+        }
+        if !status.is_success() {
+            return Err(FerrumenaError::Http { status, url });
+        }
+        Ok(resp)
+    }
+
+    /// 读取文本响应，并检查是否为 Cloudflare 验证页
+    async fn text_checked(resp: Response) -> Result<String> {
+        let url = resp.url().to_string();
+        let text = resp.text().await?;
+        if text.contains("Just a moment...") && text.contains("challenge-platform") {
+            return Err(FerrumenaError::Cloudflare { url });
+        }
+        Ok(text)
+    }
+
     /// 获取首页 HTML
     pub async fn fetch_home(&self) -> Result<String> {
+        self.retry
+            .run(
+                "首页请求",
+                |e| self.emit(e),
+                || async {
+                    self.limiter.until_ready().await;
+
+                    let resp = self
+                        .send_checked(self.client.get(&self.config.base_url))
+                        .await?;
+                    Self::text_checked(resp).await
+                },
+            )
+            .await
+    }
+
+    /// 获取搜索结果页，按配置选择后端，暂时性错误按重试策略重试
+    pub async fn fetch_page(&self, page: u32, req: &SearchRequest) -> Result<PageResponse> {
+        let what = format!("页面 {}", page);
+        let json = || {
+            self.retry
+                .run(&what, |e| self.emit(e), || self.fetch_page_json(page, req))
+        };
+        let html = || {
+            self.retry
+                .run(&what, |e| self.emit(e), || self.fetch_page_html(page, req))
+        };
+
+        match self.config.backend {
+            SearchBackend::Json => json().await,
+            SearchBackend::Html => html().await,
+            SearchBackend::Auto => {
+                if !self.json_unavailable.load(Ordering::Relaxed) {
+                    match json().await {
+                        Ok(resp) => return Ok(resp),
+                        // 只有 API 本身不可用时才回退；其余错误（如搜索句有误导致的 400、
+                        // 重试耗尽的暂时性错误）换后端也无济于事，且不应影响共用客户端的其它搜索
+                        Err(e) if !Self::api_unavailable(&e) => return Err(e),
+                        Err(e) => {
+                            // 只提示一次，之后全部回退到 HTML
+                            if !self.json_unavailable.swap(true, Ordering::Relaxed) {
+                                self.emit(DownloadEvent::BackendFallback { error: &e });
+                            }
+                        }
+                    }
+                }
+                html().await
+            }
+        }
+    }
+
+    /// 错误是否说明站点没有可用的 JSON API（不存在、被禁用或返回的不是 JSON）
+    fn api_unavailable(error: &FerrumenaError) -> bool {
+        match error {
+            FerrumenaError::ApiParse(_) | FerrumenaError::NotFound { .. } => true,
+            FerrumenaError::Http { status, .. } => matches!(
+                *status,
+                StatusCode::FORBIDDEN
+                    | StatusCode::METHOD_NOT_ALLOWED
+                    | StatusCode::NOT_IMPLEMENTED
+            ),
+            _ => false,
+        }
+    }
+
+    /// 以异步流的形式逐张返回整个搜索的结果
+    ///
+    /// 内部按页抓取，自动处理翻页（含游标翻页）、总数与 `limit` 截断，
+    /// 限速与重试同 [`fetch_page`](Self::fetch_page)。
+    /// 某一页重试耗尽后产出该错误并结束
+    ///
+    /// ```no_run
+    /// use futures::{StreamExt, TryStreamExt};
+    /// # async fn example(client: &ferrumena::PhilomenaClient) -> ferrumena::Result<()> {
+    /// let req = ferrumena::SearchRequest::builder("safe").limit(100).build()?;
+    /// let ids: Vec<u32> = client
+    ///     .search(req)
+    ///     .map_ok(|img| img.id)
+    ///     .try_collect()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn search(&self, mut req: SearchRequest) -> impl Stream<Item = Result<ImageItem>> + '_ {
+        // 随机排序的每一页必须使用同一个种子
+        req.ensure_seed();
+        let state = SearchState {
+            target: req.limit,
+            req,
+            page: 1,
+            emitted: 0,
+            buffer: VecDeque::new(),
+            exhausted: false,
+        };
+
+        stream::try_unfold(state, move |mut st| async move {
+            loop {
+                if st.target.is_some_and(|t| st.emitted >= t) {
+                    return Ok(None);
+                }
+                if let Some(img) = st.buffer.pop_front() {
+                    st.emitted += 1;
+                    return Ok(Some((img, st)));
+                }
+                if st.exhausted {
+                    return Ok(None);
+                }
+
+                let resp = self.fetch_page(st.page, &st.req).await?;
+                // 第一页确定实际数量：limit 与总数取小
+                if st.page == 1
+                    && st.req.cursor.is_none()
+                    && let Some(total) = resp.total
+                {
+                    st.target = Some(st.target.map_or(total, |l| l.min(total)));
+                }
+                // 不满一页说明已经是最后一页
+                st.exhausted = resp.images.len() < st.req.per_page as usize;
+                match st.req.pagination {
+                    PaginationMode::Page => st.page += 1,
+                    // 游标翻页始终请求第一页，由追加的条件推进
+                    PaginationMode::Cursor => {
+                        if !st.exhausted
+                            && let Some(last) = resp.images.last()
+                        {
+                            st.req = st.req.after(last)?;
+                        }
+                    }
+                }
+                st.buffer.extend(resp.images);
+            }
+        })
+    }
+
+    /// 构建搜索参数（两种后端共用）
+    fn search_params(&self, page: u32, req: &SearchRequest) -> String {
+        format!(
+            "page={}&per_page={}&sd={}&sf={}&filter_id={}&q={}",
+            page,
+            req.per_page,
+            req.sort_direction,
+            req.sort_param(),
+            req.filter_id.unwrap_or(self.config.filter_id),
+            urlencoding::encode(&req.effective_query()),
+        )
+    }
+
+    /// 通过 JSON API 获取搜索结果页
+    async fn fetch_page_json(&self, page: u32, req: &SearchRequest) -> Result<PageResponse> {
         self.limiter.until_ready().await;
 
-        let resp = self
-            .client
-            .get(&self.config.base_url)
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(resp)
+        let api_url = format!(
+            "{}api/v1/json/search/images?{}",
+            self.config.base_url,
+            self.search_params(page, req)
+        );
+
+        let resp = self.send_checked(self.client.get(&api_url)).await?;
+        let body = Self::text_checked(resp).await?;
+        let resp: ApiSearchResponse = serde_json::from_str(&body)?;
+
+        Ok(PageResponse {
+            // JSON API 每页都带有总数
+            total: Some(resp.total),
+            images: resp.images.into_iter().map(ImageItem::from).collect(),
+        })
     }
 
-    /// 获取搜索结果页
-    pub async fn fetch_page(&self, page: u32, args: &Args) -> Result<PageResponse> {
+    /// 通过解析 `/search` 页面 HTML 获取搜索结果页
+    async fn fetch_page_html(&self, page: u32, req: &SearchRequest) -> Result<PageResponse> {
         self.limiter.until_ready().await;
 
         // 构建搜索 URL
         let search_url = format!(
-            "{}search?page={}&per_page={}&sd={}&sf={}&filter_id={}&q={}",
+            "{}search?{}",
             self.config.base_url,
-            page,
-            args.per_page,
-            args.sort_direction,
-            args.sort_field.to_api_string(),
-            args.filter_id.unwrap_or(self.config.filter_id),
-            urlencoding::encode(&args.query),
+            self.search_params(page, req)
         );
 
         // 获取 HTML
-        let html = self.client.get(&search_url).send().await?.text().await?;
+        let resp = self.send_checked(self.client.get(&search_url)).await?;
+        let html = Self::text_checked(resp).await?;
 
         // 解析 HTML
         let document = scraper::Html::parse_document(&html);
@@ -137,17 +389,45 @@ impl PhilomenaClient {
 
             // 从 URL 推断文件格式
             let format = view_url
-                .split('.')
-                .last()
+                .rsplit('.')
+                .next()
                 .ok_or_else(|| {
                     FerrumenaError::Logic(format!("无法推断文件格式，URL 不含扩展名: {}", view_url))
                 })?
                 .to_string();
 
+            // 其余元数据均为可选属性，缺失时留空
+            let attr = |name: &str| element.value().attr(name);
+            let source_urls = attr("data-source-url")
+                .filter(|s| !s.is_empty())
+                .map(|s| vec![s.to_string()])
+                .unwrap_or_default();
+
             images.push(ImageItem {
                 id,
                 representations,
                 view_url,
+                tags: attr("data-image-tag-aliases")
+                    .map(split_tag_list)
+                    .unwrap_or_default(),
+                tag_ids: attr("data-image-tags")
+                    .map(parse_tag_ids)
+                    .unwrap_or_default(),
+                score: parse_attr(attr("data-score")),
+                upvotes: parse_attr(attr("data-upvotes")),
+                downvotes: parse_attr(attr("data-downvotes")),
+                faves: parse_attr(attr("data-faves")),
+                uploader: None,
+                uploader_id: None,
+                created_at: parse_timestamp(attr("data-created-at")),
+                updated_at: None,
+                width: parse_attr(attr("data-width")),
+                height: parse_attr(attr("data-height")),
+                mime_type: mime_from_format(&format).map(str::to_string),
+                size: None,
+                source_urls,
+                sha512_hash: None,
+                orig_sha512_hash: None,
                 format,
             });
         }
@@ -162,3 +442,16 @@ impl PhilomenaClient {
         Ok(PageResponse { total, images })
     }
 }
+
+/// [`PhilomenaClient::search`] 的翻页状态
+struct SearchState {
+    req: SearchRequest,
+    // 下一次要抓取的页码，游标翻页时固定为 1
+    page: u32,
+    // 已产出的图片数
+    emitted: u32,
+    // 计划产出的图片数，None 表示直到没有结果
+    target: Option<u32>,
+    buffer: VecDeque<ImageItem>,
+    exhausted: bool,
+}
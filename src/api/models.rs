@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// 传递给下载器的图片任务
@@ -5,19 +7,118 @@ pub struct DownloadTask {
     pub id: u32,
     pub url: String,
     pub file_ext: String,
+    /// 实际使用的 representation 键（回退到 view_url 时为 "view"）
+    pub representation: String,
+    pub image: ImageItem,
 }
 
 /// 单页图片数据
 pub struct PageResponse {
-    /// 总数，仅在 page=1 时提取，否则为 None
+    /// 总数，HTML 后端仅在 page=1 时提取，否则为 None
     pub total: Option<u32>,
     pub images: Vec<ImageItem>,
 }
 
 /// 图片条目
+///
+/// JSON API 可以填满所有字段；HTML 后端只能拿到缩略图容器上的
+/// `data-*` 属性，拿不到的字段为 `None` / 空
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageItem {
     pub id: u32,
     pub representations: HashMap<String, String>,
     pub view_url: String,
     pub format: String,
+
+    /// 标签名（HTML 后端来自 `data-image-tag-aliases`，可能包含别名）
+    pub tags: Vec<String>,
+    pub tag_ids: Vec<u32>,
+
+    pub score: Option<i32>,
+    pub upvotes: Option<u32>,
+    pub downvotes: Option<u32>,
+    pub faves: Option<u32>,
+
+    /// 上传者，匿名上传时为 None
+    pub uploader: Option<String>,
+    pub uploader_id: Option<u32>,
+
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub mime_type: Option<String>,
+    /// 文件大小（字节）
+    pub size: Option<u64>,
+    pub source_urls: Vec<String>,
+
+    /// 站点存储文件的 SHA-512
+    pub sha512_hash: Option<String>,
+    /// 原始上传文件的 SHA-512
+    pub orig_sha512_hash: Option<String>,
+}
+
+/// `/api/v1/json/search/images` 的响应体
+#[derive(Deserialize)]
+pub struct ApiSearchResponse {
+    pub images: Vec<ApiImage>,
+    pub total: u32,
+}
+
+/// JSON API 中的单张图片（仅保留需要的字段）
+#[derive(Deserialize)]
+pub struct ApiImage {
+    pub id: u32,
+    pub representations: HashMap<String, String>,
+    pub view_url: String,
+    pub format: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub tag_ids: Vec<u32>,
+    pub score: Option<i32>,
+    pub upvotes: Option<u32>,
+    pub downvotes: Option<u32>,
+    pub faves: Option<u32>,
+    pub uploader: Option<String>,
+    pub uploader_id: Option<u32>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub mime_type: Option<String>,
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub source_urls: Vec<String>,
+    pub sha512_hash: Option<String>,
+    pub orig_sha512_hash: Option<String>,
+}
+
+impl From<ApiImage> for ImageItem {
+    fn from(img: ApiImage) -> Self {
+        Self {
+            id: img.id,
+            representations: img.representations,
+            view_url: img.view_url,
+            format: img.format,
+            tags: img.tags,
+            tag_ids: img.tag_ids,
+            score: img.score,
+            upvotes: img.upvotes,
+            downvotes: img.downvotes,
+            faves: img.faves,
+            uploader: img.uploader,
+            uploader_id: img.uploader_id,
+            created_at: img.created_at,
+            updated_at: img.updated_at,
+            width: img.width,
+            height: img.height,
+            mime_type: img.mime_type,
+            size: img.size,
+            source_urls: img.source_urls,
+            sha512_hash: img.sha512_hash,
+            orig_sha512_hash: img.orig_sha512_hash,
+        }
+    }
 }
@@ -0,0 +1,290 @@
+use governor::{
+    Quota, RateLimiter,
+    clock::DefaultClock,
+    state::{InMemoryState, NotKeyed, keyed::DefaultKeyedStateStore},
+};
+use reqwest::{Response, StatusCode, header};
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+type DirectLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
+/// 没有 Retry-After 时的默认暂停时长
+const DEFAULT_PAUSE: Duration = Duration::from_secs(10);
+
+/// Retry-After 的上限，防止异常值让程序长时间挂起
+const MAX_PAUSE: Duration = Duration::from_secs(600);
+
+/// 两次降速之间的最小间隔，同一波 429 只降一次
+const THROTTLE_COOLDOWN: Duration = Duration::from_secs(2);
+
+/// 没有再被限流多久之后恢复一档速率
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 限速器观察到的速率变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateChange {
+    /// 被限流，暂停 `pause` 后以 `rps` 继续；不限速时 `rps` 为 0，只暂停
+    Limited { rps: u32, pause: Duration },
+    /// 速率恢复一档
+    Recovered { rps: u32 },
+}
+
+/// 自适应限速器
+///
+/// 收到 429 / 503 时暂停所有请求（时长取自 `Retry-After`），并把速率减半；
+/// 之后每 30 秒没有再被限流就恢复 1 RPS，直到配置的上限
+pub struct AdaptiveLimiter {
+    // 0 表示不限速，被限流时只暂停
+    max_rps: u32,
+    current_rps: AtomicU32,
+    // governor 的 Quota 不可修改，调速时整体替换
+    limiter: RwLock<Option<Arc<DirectLimiter>>>,
+    paused_until: Mutex<Option<Instant>>,
+    last_adjust: Mutex<Instant>,
+}
+
+impl AdaptiveLimiter {
+    /// `max_rps` 为每秒请求数上限，0 表示不限速
+    pub fn new(max_rps: u32) -> Self {
+        Self {
+            max_rps,
+            current_rps: AtomicU32::new(max_rps),
+            limiter: RwLock::new(
+                NonZeroU32::new(max_rps)
+                    .map(|rps| Arc::new(RateLimiter::direct(Quota::per_second(rps)))),
+            ),
+            paused_until: Mutex::new(None),
+            last_adjust: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// 当前生效的每秒请求数，不限速时为 0
+    pub fn current_rps(&self) -> u32 {
+        self.current_rps.load(Ordering::Relaxed)
+    }
+
+    /// 等待暂停结束（不占用速率配额）
+    pub async fn wait_pause(&self) {
+        loop {
+            let until = *self.paused_until.lock().unwrap();
+            match until {
+                Some(t) if t > Instant::now() => tokio::time::sleep_until(t).await,
+                _ => return,
+            }
+        }
+    }
+
+    /// 等待暂停结束并获取一个请求配额
+    pub async fn until_ready(&self) {
+        self.wait_pause().await;
+        let limiter = self.limiter.read().unwrap().clone();
+        if let Some(limiter) = limiter {
+            limiter.until_ready().await;
+        }
+    }
+
+    /// 根据响应调整节奏：429 / 503 触发暂停和降速，成功响应逐步恢复
+    ///
+    /// 速率发生变化时返回变化，由调用方转换为事件
+    pub fn observe(&self, resp: &Response) -> Option<RateChange> {
+        let status = resp.status();
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            self.on_throttled(retry_after(resp))
+        } else if status.is_success() {
+            self.on_success()
+        } else {
+            None
+        }
+    }
+
+    fn on_throttled(&self, retry_after: Option<Duration>) -> Option<RateChange> {
+        let pause = retry_after.unwrap_or(DEFAULT_PAUSE).min(MAX_PAUSE);
+        let until = Instant::now() + pause;
+        {
+            let mut paused = self.paused_until.lock().unwrap();
+            if paused.is_none_or(|t| t < until) {
+                *paused = Some(until);
+            }
+        }
+
+        let mut last = self.last_adjust.lock().unwrap();
+        if last.elapsed() < THROTTLE_COOLDOWN {
+            return None;
+        }
+        *last = Instant::now();
+
+        if self.max_rps == 0 {
+            return Some(RateChange::Limited { rps: 0, pause });
+        }
+        let new_rps = (self.current_rps() / 2).max(1);
+        self.set_rps(new_rps);
+        Some(RateChange::Limited {
+            rps: new_rps,
+            pause,
+        })
+    }
+
+    fn on_success(&self) -> Option<RateChange> {
+        if self.current_rps() >= self.max_rps {
+            return None;
+        }
+        let mut last = self.last_adjust.lock().unwrap();
+        if last.elapsed() < RECOVERY_INTERVAL {
+            return None;
+        }
+        *last = Instant::now();
+
+        let new_rps = (self.current_rps() + 1).min(self.max_rps);
+        self.set_rps(new_rps);
+        Some(RateChange::Recovered { rps: new_rps })
+    }
+
+    fn set_rps(&self, rps: u32) {
+        let Some(nz) = NonZeroU32::new(rps) else {
+            return;
+        };
+        self.current_rps.store(rps, Ordering::Relaxed);
+        *self.limiter.write().unwrap() = Some(Arc::new(RateLimiter::direct(Quota::per_second(nz))));
+    }
+}
+
+/// 解析 `Retry-After`，支持秒数和 HTTP 日期两种格式
+pub(crate) fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delta = date.signed_duration_since(chrono::Utc::now());
+    Some(delta.to_std().unwrap_or(Duration::ZERO))
+}
+
+type KeyedLimiter = RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock>;
+
+/// 图片（CDN）请求的限速器，按主机名分别计数
+///
+/// 与爬页限速器互相独立：图片和页面通常不在同一个主机上，
+/// 请求数和带宽两个维度都可以单独关闭（配置为 0）。
+/// 每个主机各有一个 [`AdaptiveLimiter`]，CDN 的 429 / 503 只让该主机暂停和降速
+pub struct HostLimiter {
+    rps: u32,
+    requests: Mutex<HashMap<String, Arc<AdaptiveLimiter>>>,
+    bytes: Option<KeyedLimiter>,
+    // 单次最多申请的字节配额，等于每秒字节数
+    byte_burst: NonZeroU32,
+}
+
+impl HostLimiter {
+    /// `rps` 为每个主机每秒请求数，`bytes_per_sec` 为每个主机每秒字节数，0 表示不限
+    pub fn new(rps: u32, bytes_per_sec: u64) -> Self {
+        let bps = NonZeroU32::new(bytes_per_sec.min(u32::MAX as u64) as u32);
+        Self {
+            rps,
+            requests: Mutex::new(HashMap::new()),
+            bytes: bps.map(|b| RateLimiter::keyed(Quota::per_second(b))),
+            byte_burst: bps.unwrap_or(NonZeroU32::MAX),
+        }
+    }
+
+    /// 等待主机 host 的限流暂停结束并获取请求配额
+    pub async fn until_request_ready(&self, host: &str) {
+        self.host(host).until_ready().await;
+    }
+
+    /// 根据主机 host 的响应调整该主机的节奏，见 [`AdaptiveLimiter::observe`]
+    pub fn observe(&self, host: &str, resp: &Response) -> Option<RateChange> {
+        self.host(host).observe(resp)
+    }
+
+    fn host(&self, host: &str) -> Arc<AdaptiveLimiter> {
+        let mut requests = self.requests.lock().unwrap();
+        let limiter = requests
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(AdaptiveLimiter::new(self.rps)));
+        Arc::clone(limiter)
+    }
+
+    /// 消耗主机 host 的 n 字节带宽配额，超过单次上限时分批申请
+    pub async fn consume_bytes(&self, host: &str, n: usize) {
+        let Some(limiter) = &self.bytes else {
+            return;
+        };
+        let key = host.to_string();
+        let mut remaining = n as u64;
+        while remaining > 0 {
+            let batch = remaining.min(self.byte_burst.get() as u64);
+            if let Some(batch_nz) = NonZeroU32::new(batch as u32) {
+                // batch 不超过 burst，不会返回 InsufficientCapacity
+                let _ = limiter.until_key_n_ready(&key, batch_nz).await;
+            }
+            remaining -= batch;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle_halves_and_pauses() {
+        let limiter = AdaptiveLimiter::new(8);
+        // 刚创建时处于调整冷却期内
+        *limiter.last_adjust.lock().unwrap() = Instant::now() - THROTTLE_COOLDOWN;
+        assert_eq!(
+            limiter.on_throttled(Some(Duration::from_secs(3))),
+            Some(RateChange::Limited {
+                rps: 4,
+                pause: Duration::from_secs(3)
+            })
+        );
+        // 同一波限流只降一次
+        assert_eq!(limiter.on_throttled(None), None);
+        assert_eq!(limiter.current_rps(), 4);
+        assert!(limiter.paused_until.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn unlimited_only_pauses() {
+        let limiter = AdaptiveLimiter::new(0);
+        *limiter.last_adjust.lock().unwrap() = Instant::now() - THROTTLE_COOLDOWN;
+        assert_eq!(
+            limiter.on_throttled(None),
+            Some(RateChange::Limited {
+                rps: 0,
+                pause: DEFAULT_PAUSE
+            })
+        );
+        assert_eq!(limiter.current_rps(), 0);
+        assert_eq!(limiter.on_success(), None);
+    }
+
+    #[test]
+    fn hosts_are_throttled_independently() {
+        let limiter = HostLimiter::new(16, 0);
+        let cdn = limiter.host("cdn.example.org");
+        *cdn.last_adjust.lock().unwrap() = Instant::now() - THROTTLE_COOLDOWN;
+        cdn.on_throttled(None);
+
+        assert_eq!(limiter.host("cdn.example.org").current_rps(), 8);
+        assert_eq!(limiter.host("other.example.org").current_rps(), 16);
+        assert!(
+            limiter
+                .host("other.example.org")
+                .paused_until
+                .lock()
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -1,16 +1,25 @@
-use clap::{Parser, ValueEnum};
-use rand::RngExt;
-use std::fmt;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use std::time::Duration;
+
+use ferrumena::partition::PartitionKey;
+use ferrumena::search::PaginationMode;
+use ferrumena::watch::{WatchQuery, parse_duration};
+use ferrumena::{FerrumenaConfig, LimitMode, SearchBackend, SearchRequest, SortField, SortOrder};
 
 #[derive(Parser, Debug, Clone)]
-#[command(author, version, about = "Ferrumena: Philomena 异步下载器")]
+#[command(
+    author,
+    version,
+    about = "Ferrumena: Philomena 异步下载器",
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
-    /// 搜索句（必填）
+    /// 搜索句（不使用子命令时必填）
     /// (例: "pony OR safe")
     /// 搜索句法请参考：<https://trixiebooru.org/pages/search_syntax>
-    #[arg(short, long, allow_hyphen_values = true)]
-    pub query: String,
+    #[arg(short, long, allow_hyphen_values = true, required = true)]
+    pub query: Option<String>,
 
     /// 排序字段
     #[arg(short = 'f', long, visible_alias = "sf", value_enum, default_value_t = SortField::Id)]
@@ -29,11 +38,28 @@ pub struct Args {
     )]
     pub per_page: u32,
 
+    /// 翻页方式
+    /// page: 按页码翻页，可多页并发
+    /// cursor: 按上一页最后一张图片追加条件翻页，结果稳定，不受新上传影响
+    #[arg(long, value_enum, default_value_t = PaginationMode::Page)]
+    pub pagination: PaginationMode,
+
+    /// 随机排序的种子，相同种子得到相同的结果顺序
+    /// 默认：随机生成（会打印出来，便于复现）
+    #[arg(long)]
+    pub seed: Option<u32>,
+
     /// 本次运行的最大下载张数
     /// 默认：所有结果
     #[arg(short, long)]
     pub limit: Option<u32>,
 
+    /// --limit 的计数方式
+    /// total: 只处理前 N 张搜索结果，已存在的也计入
+    /// new: 只计新下载的文件，跳过已存在的继续往后翻页
+    #[arg(long, value_enum)]
+    pub limit_mode: Option<LimitMode>,
+
     /// 目标站点 URL
     #[arg(long)]
     pub base_url: Option<String>,
@@ -54,14 +80,51 @@ pub struct Args {
     #[arg(short, long)]
     pub rps: Option<u32>,
 
+    /// 每个图片主机每秒请求数，0 表示不限
+    #[arg(long)]
+    pub image_rps: Option<u32>,
+
+    /// 每个图片主机每秒带宽，0 表示不限
+    /// 支持 K / M / G 后缀（例: 512K, 4M）
+    #[arg(long, value_name = "BYTES", value_parser = parse_byte_size)]
+    pub image_bandwidth: Option<u64>,
+
     /// 并发下载任务数
     #[arg(short, long)]
     pub concurrency: Option<u32>,
 
+    /// 同时抓取搜索页的任务数
+    #[arg(long)]
+    pub page_workers: Option<u32>,
+
     /// 页面抓取连续失败上限
     #[arg(long)]
     pub max_failures: Option<u32>,
 
+    /// 分区数，大于 1 时把搜索切成互不重叠的范围并行抓取
+    #[arg(long)]
+    pub partitions: Option<u32>,
+
+    /// 分区依据的字段
+    #[arg(long, value_enum)]
+    pub partition_by: Option<PartitionKey>,
+
+    /// 单个请求最多尝试次数（含第一次）
+    #[arg(long)]
+    pub retries: Option<u32>,
+
+    /// 第一次重试前的等待毫秒数，之后指数增长
+    #[arg(long, value_name = "MS")]
+    pub retry_delay: Option<u64>,
+
+    /// 单次重试等待的上限毫秒数
+    #[arg(long, value_name = "MS")]
+    pub retry_max_delay: Option<u64>,
+
+    /// 重试等待时间的随机抖动比例 (0.0-1.0)
+    #[arg(long)]
+    pub retry_jitter: Option<f64>,
+
     /// 图片质量级别（representation）
     /// 例如 full / tall/ large / medium / small / thumb
     #[arg(long, visible_alias = "rep")]
@@ -70,60 +133,196 @@ pub struct Args {
     /// 文件保存路径
     #[arg(short = 'o', long)]
     pub save_path: Option<PathBuf>,
+
+    /// 文件名模板，`/` 分隔子目录
+    /// 例如 "{id}__{artist}__{tags:5}.{ext}" 或 "{created_at:%Y}/{id}.{ext}"
+    #[arg(short = 't', long, visible_alias = "template")]
+    pub filename_template: Option<String>,
+
+    /// 同时写入 `{id}.json` 元数据文件
+    #[arg(long)]
+    pub sidecar: bool,
+
+    /// 跳过 SHA-512 完整性校验
+    #[arg(long)]
+    pub no_verify: bool,
+
+    /// 增量同步：只抓取上次完整运行之后上传的图片
+    /// 同步位置按站点、搜索句和过滤器记录在 `.ferrumena.db` 中
+    #[arg(long)]
+    pub sync: bool,
+
+    /// 无视已有的下载记录，重新扫描保存目录重建 `.ferrumena.db`
+    #[arg(long)]
+    pub rebuild_state: bool,
+
+    /// 搜索后端
+    /// auto: 优先 JSON API，失败时回退到 HTML 解析
+    #[arg(short = 'b', long, value_enum)]
+    pub backend: Option<SearchBackend>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// 子命令，写在全局参数之后
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// 常驻运行，按各自的间隔轮询一组搜索，只下载新上传的图片
+    Watch(WatchArgs),
+    /// 执行 TOML 任务文件中描述的多个下载任务
+    Run(RunArgs),
 }
 
-#[derive(ValueEnum, Clone, Debug)]
-pub enum SortField {
-    Id,
-    UpdatedAt,
-    AspectRatio,
-    Faves,
-    Upvotes,
-    Downvotes,
-    Score,
-    Relevance,
-    Random,
-    Width,
-    Height,
-    CommentCount,
-    TagCount,
-    Pixels,
-    Size,
-    Duration,
+#[derive(clap::Args, Debug, Clone)]
+pub struct RunArgs {
+    /// 任务文件路径 (例: jobs.toml)
+    pub file: PathBuf,
+
+    /// 同时运行的任务数，覆盖任务文件中的 parallel
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub parallel: Option<u32>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct WatchArgs {
+    /// 要轮询的搜索，格式为 `<间隔>=<搜索句>` 或单独的搜索句
+    /// (例: "10m=artist:example" "2h=safe, pony")
+    #[arg(required = true)]
+    pub queries: Vec<WatchQuery>,
+
+    /// 未指定间隔的搜索使用的轮询间隔，支持 s / m / h / d 后缀
+    #[arg(short, long, default_value = "30m", value_parser = parse_duration)]
+    pub interval: Duration,
 }
 
-impl SortField {
-    pub fn to_api_string(&self) -> String {
-        match self {
-            // Relevance -> _score
-            Self::Relevance => "_score".to_string(),
-            // Random -> random:随机数
-            Self::Random => {
-                let seed: u32 = rand::rng().random();
-                format!("random:{}", seed)
-            }
-            // 多词字段
-            Self::UpdatedAt => "updated_at".to_string(),
-            Self::AspectRatio => "aspect_ratio".to_string(),
-            Self::CommentCount => "comment_count".to_string(),
-            Self::TagCount => "tag_count".to_string(),
-            // 单词字段
-            _ => format!("{:?}", self).to_lowercase(),
+impl Args {
+    /// 用命令行参数覆盖环境变量中的配置
+    pub fn apply(&self, mut config: FerrumenaConfig) -> FerrumenaConfig {
+        if let Some(ref url) = self.base_url {
+            config.base_url = url.clone();
+        }
+        if let Some(id) = self.filter_id {
+            config.filter_id = id;
+        }
+        if let Some(ref ua) = self.user_agent {
+            config.user_agent = ua.clone();
+        }
+        if let Some(ref ck) = self.cookie {
+            config.cookie = ck.clone();
+        }
+        if let Some(r) = self.rps {
+            config.rps = r;
+        }
+        if let Some(r) = self.image_rps {
+            config.image_rps = r;
+        }
+        if let Some(b) = self.image_bandwidth {
+            config.image_bandwidth = b;
+        }
+        if let Some(c) = self.concurrency {
+            config.concurrency = c;
+        }
+        if let Some(p) = self.page_workers {
+            config.page_workers = p;
+        }
+        if let Some(m) = self.max_failures {
+            config.max_failures = m;
+        }
+        if let Some(m) = self.limit_mode {
+            config.limit_mode = m;
+        }
+        if let Some(n) = self.partitions {
+            config.partitions = n;
+        }
+        if let Some(k) = self.partition_by {
+            config.partition_by = k;
         }
+        if let Some(n) = self.retries {
+            config.retry_max_attempts = n;
+        }
+        if let Some(ms) = self.retry_delay {
+            config.retry_base_delay_ms = ms;
+        }
+        if let Some(ms) = self.retry_max_delay {
+            config.retry_max_delay_ms = ms;
+        }
+        if let Some(j) = self.retry_jitter {
+            config.retry_jitter = j;
+        }
+        if let Some(ref representation) = self.representation {
+            config.representation = representation.clone();
+        }
+        if let Some(ref p) = self.save_path {
+            config.save_path = p.clone();
+        }
+        if let Some(ref t) = self.filename_template {
+            config.filename_template = t.clone();
+        }
+        if self.sidecar {
+            config.sidecar = true;
+        }
+        if self.no_verify {
+            config.verify = false;
+        }
+        if self.sync {
+            config.sync = true;
+        }
+        if self.rebuild_state {
+            config.rebuild_state = true;
+        }
+        if let Some(b) = self.backend {
+            config.backend = b;
+        }
+        config.normalized()
+    }
+
+    /// 以命令行的排序、分页等参数为 `query` 生成搜索请求
+    pub fn search_for(&self, query: &str) -> SearchRequest {
+        let mut search = SearchRequest {
+            query: query.to_string(),
+            sort_field: self.sort_field.clone(),
+            sort_direction: self.sort_direction.clone(),
+            per_page: self.per_page,
+            filter_id: self.filter_id,
+            limit: self.limit,
+            seed: self.seed,
+            pagination: self.pagination,
+            partition: None,
+            cursor: None,
+            since_id: None,
+        };
+        search.ensure_seed();
+        search
     }
 }
 
-#[derive(clap::ValueEnum, Clone, Debug)]
-pub enum SortOrder {
-    Asc,
-    Desc,
+impl From<&Args> for SearchRequest {
+    fn from(args: &Args) -> Self {
+        args.search_for(args.query.as_deref().unwrap_or_default())
+    }
 }
 
-impl fmt::Display for SortOrder {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            SortOrder::Asc => write!(f, "asc"),
-            SortOrder::Desc => write!(f, "desc"),
-        }
+/// 解析带 K / M / G 后缀（1024 进制）的字节数，例如 `512K`、`4M`
+pub fn parse_byte_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let (num, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(pos) => value.split_at(pos),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("无法识别的单位: {}", unit)),
+    };
+    let num: f64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("无效的字节数: {}", value))?;
+    if num < 0.0 {
+        return Err(format!("字节数不能为负: {}", value));
     }
+    Ok((num * multiplier as f64) as u64)
 }
@@ -1,10 +1,13 @@
 use serde::Deserialize;
 use std::path::PathBuf;
 
-use crate::cli;
+use crate::downloader::LimitMode;
+use crate::error::{FerrumenaError, Result};
+use crate::naming;
+use crate::partition::PartitionKey;
 
 /// Ferrumena 的核心配置结构体
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct FerrumenaConfig {
     /// 目标站点，默认 <https://trixiebooru.org>
     #[serde(default = "default_base_url")]
@@ -26,14 +29,54 @@ pub struct FerrumenaConfig {
     #[serde(default = "default_rps")]
     pub rps: u32,
 
+    /// 每个图片主机每秒请求数，0 表示不限，默认 16
+    #[serde(default = "default_image_rps")]
+    pub image_rps: u32,
+
+    /// 每个图片主机每秒字节数，0 表示不限，默认 0
+    #[serde(default)]
+    pub image_bandwidth: u64,
+
     /// 并发下载任务数
     #[serde(default = "default_concurrency")]
     pub concurrency: u32,
 
+    /// 同时抓取搜索页的任务数，默认 2
+    #[serde(default = "default_page_workers")]
+    pub page_workers: u32,
+
     /// 页面抓取连续失败上限
     #[serde(default = "default_max_failures")]
     pub max_failures: u32,
 
+    /// `--limit` 的计数方式，默认 total
+    #[serde(default)]
+    pub limit_mode: LimitMode,
+
+    /// 分区数，大于 1 时把搜索切成互不重叠的范围并行抓取，默认 1（不分区）
+    #[serde(default = "default_partitions")]
+    pub partitions: u32,
+
+    /// 分区依据的字段，默认 id
+    #[serde(default)]
+    pub partition_by: PartitionKey,
+
+    /// 单个请求最多尝试次数（含第一次），默认 4
+    #[serde(default = "default_retry_max_attempts")]
+    pub retry_max_attempts: u32,
+
+    /// 第一次重试前的等待毫秒数，之后指数增长，默认 500
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+
+    /// 单次重试等待的上限毫秒数，默认 30000
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub retry_max_delay_ms: u64,
+
+    /// 重试等待时间的随机抖动比例，默认 0.2
+    #[serde(default = "default_retry_jitter")]
+    pub retry_jitter: f64,
+
     /// 图片质量级别（representation），默认 full
     #[serde(default = "default_representation")]
     pub representation: String,
@@ -41,6 +84,54 @@ pub struct FerrumenaConfig {
     /// 文件保存路径
     #[serde(default = "default_save_path")]
     pub save_path: PathBuf,
+
+    /// 文件名模板，默认 `{id}.{ext}`
+    #[serde(default = "default_filename_template")]
+    pub filename_template: String,
+
+    /// 是否为每张图片写入 `{id}.json` 元数据，默认 false
+    #[serde(default)]
+    pub sidecar: bool,
+
+    /// 是否用站点提供的 SHA-512 校验下载结果，默认 true
+    #[serde(default = "default_verify")]
+    pub verify: bool,
+
+    /// 是否增量同步，只抓取上次完整运行之后上传的图片，默认 false
+    #[serde(default)]
+    pub sync: bool,
+
+    /// 是否无视已有的下载记录，从目录扫描重建状态数据库，默认 false
+    #[serde(default)]
+    pub rebuild_state: bool,
+
+    /// 搜索后端，默认 auto（优先 JSON API，失败时回退到 HTML 解析）
+    #[serde(default)]
+    pub backend: SearchBackend,
+}
+
+/// 搜索结果的获取方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SearchBackend {
+    /// 优先 JSON API，失败时回退到 HTML
+    #[default]
+    Auto,
+    /// 仅使用 `/api/v1/json/search/images`
+    Json,
+    /// 仅解析 `/search` 页面 HTML
+    Html,
+}
+
+impl std::fmt::Display for SearchBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SearchBackend::Auto => write!(f, "auto"),
+            SearchBackend::Json => write!(f, "json"),
+            SearchBackend::Html => write!(f, "html"),
+        }
+    }
 }
 
 // 默认值提供函数
@@ -65,14 +156,42 @@ fn default_rps() -> u32 {
     8
 }
 
+fn default_image_rps() -> u32 {
+    16
+}
+
 fn default_concurrency() -> u32 {
     64
 }
 
+fn default_page_workers() -> u32 {
+    2
+}
+
 fn default_max_failures() -> u32 {
     5
 }
 
+fn default_partitions() -> u32 {
+    1
+}
+
+fn default_retry_max_attempts() -> u32 {
+    4
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_retry_max_delay_ms() -> u64 {
+    30_000
+}
+
+fn default_retry_jitter() -> f64 {
+    0.2
+}
+
 fn default_representation() -> String {
     "full".to_string()
 }
@@ -81,50 +200,33 @@ fn default_save_path() -> PathBuf {
     PathBuf::from("./ferrumena_downloads")
 }
 
+fn default_verify() -> bool {
+    true
+}
+
+fn default_filename_template() -> String {
+    naming::DEFAULT_TEMPLATE.to_string()
+}
+
 impl FerrumenaConfig {
-    /// 加载： Default -> .env -> Environment
+    /// 加载： Default -> .env -> Environment，解析失败时使用默认配置
     pub fn load() -> Self {
+        Self::try_load().unwrap_or_default()
+    }
+
+    /// 同 [`load`](Self::load)，但把环境变量的解析错误返回给调用方
+    pub fn try_load() -> Result<Self> {
         // 加载 .env 文件（如果存在）
         let _ = dotenvy::dotenv();
 
         envy::prefixed("FERRUMENA_")
             .from_env::<FerrumenaConfig>()
-            .unwrap_or_else(|e| {
-                println!("⚠️  环境变量解析失败 ({})，将使用默认配置。", e);
-                Self::default()
-            })
+            .map_err(|e| FerrumenaError::Config(format!("环境变量解析失败 ({})", e)))
     }
 
-    /// 应用命令行参数覆盖
-    pub fn merge_with_cli(mut self, args: &cli::Args) -> Self {
-        if let Some(ref url) = args.base_url {
-            self.base_url = url.clone();
-        }
-        if let Some(id) = args.filter_id {
-            self.filter_id = id;
-        }
-        if let Some(ref ua) = args.user_agent {
-            self.user_agent = ua.clone();
-        }
-        if let Some(ref ck) = args.cookie {
-            self.cookie = ck.clone();
-        }
-        if let Some(r) = args.rps {
-            self.rps = r;
-        }
-        if let Some(c) = args.concurrency {
-            self.concurrency = c;
-        }
-        if let Some(m) = args.max_failures {
-            self.max_failures = m;
-        }
-        if let Some(ref representation) = args.representation {
-            self.representation = representation.clone();
-        }
-        if let Some(ref p) = args.save_path {
-            self.save_path = p.clone();
-        }
-
+    /// 把为空的 `user_agent`、`base_url`、`representation` 和 `filename_template`
+    /// 恢复为默认值，在应用命令行等外部覆盖之后调用
+    pub fn normalized(mut self) -> Self {
         // 不允许空 UA 和 空 URL
         if self.user_agent.trim().is_empty() {
             self.user_agent = default_user_agent();
@@ -135,6 +237,9 @@ impl FerrumenaConfig {
         if self.representation.trim().is_empty() {
             self.representation = default_representation();
         }
+        if self.filename_template.trim().is_empty() {
+            self.filename_template = default_filename_template();
+        }
 
         self
     }
@@ -149,10 +254,26 @@ impl Default for FerrumenaConfig {
             user_agent: default_user_agent(),
             cookie: String::new(),
             rps: default_rps(),
+            image_rps: default_image_rps(),
+            image_bandwidth: 0,
             concurrency: default_concurrency(),
+            page_workers: default_page_workers(),
             max_failures: default_max_failures(),
+            limit_mode: LimitMode::default(),
+            partitions: default_partitions(),
+            partition_by: PartitionKey::default(),
+            retry_max_attempts: default_retry_max_attempts(),
+            retry_base_delay_ms: default_retry_base_delay_ms(),
+            retry_max_delay_ms: default_retry_max_delay_ms(),
+            retry_jitter: default_retry_jitter(),
             representation: default_representation(),
             save_path: default_save_path(),
+            filename_template: default_filename_template(),
+            sidecar: false,
+            verify: default_verify(),
+            sync: false,
+            rebuild_state: false,
+            backend: SearchBackend::default(),
         }
     }
 }
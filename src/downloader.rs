@@ -1,158 +1,584 @@
-use crate::api::models::DownloadTask;
-use crate::cli::Args;
-use crate::{api::client::PhilomenaClient, error::Result};
-use crate::utils::compact_url_for_log;
+use crate::api::client::PhilomenaClient;
+use crate::api::models::{DownloadTask, ImageItem, PageResponse};
+use crate::config::FerrumenaConfig;
+use crate::error::{FerrumenaError, Result};
+use crate::events::{DownloadEvent, DownloadObserver, Observers, RunSummary};
+use crate::naming::FilenameTemplate;
+use crate::partition::{self, PartitionKey};
+use crate::resume::{PART_EXT, PART_META_EXT};
+use crate::search::{PaginationMode, SearchRequest};
+use crate::sidecar::{SIDECAR_EXT, SIDECAR_VERSION, Sidecar};
+use crate::state::{DownloadRecord, STATE_DB_NAME, StateDb};
+use crate::utils::ext_from_url;
+use crate::verify;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
 use std::collections::HashSet;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Instant;
+use tokio::sync::{Mutex, Semaphore, mpsc};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
-pub struct Downloader {
+mod transfer;
+
+use transfer::SavedFile;
+
+/// 下载器的运行选项
+///
+/// 默认取自 [`FerrumenaConfig`]，可通过 [`DownloaderBuilder`] 逐项覆盖，
+/// 这样多个下载器可以共用同一个客户端（及其限速器）而各自保存到不同位置
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// 文件保存路径
+    pub save_path: PathBuf,
+    /// 图片质量级别（representation）
+    pub representation: String,
+    /// 文件名模板
+    pub filename_template: String,
+    /// 并发下载任务数
+    pub concurrency: u32,
+    /// 同时抓取搜索页的任务数
+    pub page_workers: u32,
+    /// 页面抓取连续失败上限
+    pub max_failures: u32,
+    /// 搜索请求中 `limit` 的计数方式
+    pub limit_mode: LimitMode,
+    /// 分区数，大于 1 时把搜索切成互不重叠的范围并行抓取
+    pub partitions: u32,
+    /// 分区依据的字段
+    pub partition_by: PartitionKey,
+    /// 是否写入元数据 sidecar
+    pub sidecar: bool,
+    /// 是否校验 SHA-512
+    pub verify: bool,
+    /// 是否增量同步，只抓取上次完整运行之后上传的图片
+    pub sync: bool,
+    /// 是否无视已有的下载记录，从目录扫描重建状态数据库
+    pub rebuild_state: bool,
+}
+
+impl DownloadOptions {
+    pub fn from_config(config: &FerrumenaConfig) -> Self {
+        Self {
+            save_path: config.save_path.clone(),
+            representation: config.representation.clone(),
+            filename_template: config.filename_template.clone(),
+            concurrency: config.concurrency,
+            page_workers: config.page_workers,
+            max_failures: config.max_failures,
+            limit_mode: config.limit_mode,
+            partitions: config.partitions,
+            partition_by: config.partition_by,
+            sidecar: config.sidecar,
+            verify: config.verify,
+            sync: config.sync,
+            rebuild_state: config.rebuild_state,
+        }
+    }
+}
+
+/// `limit` 的计数方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum LimitMode {
+    /// 按搜索结果计数：只处理前 N 张，已存在的也计入
+    #[default]
+    Total,
+    /// 只计新下载的文件：跳过已存在的继续往后翻页，直到新下载满 N 张
+    New,
+}
+
+impl std::fmt::Display for LimitMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LimitMode::Total => write!(f, "total"),
+            LimitMode::New => write!(f, "new"),
+        }
+    }
+}
+
+/// [`Downloader`] 的构建器，通过 [`Downloader::builder`] 创建
+pub struct DownloaderBuilder {
     client: Arc<PhilomenaClient>,
-    args: Args,
-    // 存储本地已存在的 ID，用于去重
-    existing_ids: Arc<HashSet<u32>>,
+    search: SearchRequest,
+    options: DownloadOptions,
+    observers: Observers,
+    shutdown: CancellationToken,
 }
 
-impl Downloader {
-    pub async fn new(client: PhilomenaClient, args: Args) -> Result<Self> {
+impl DownloaderBuilder {
+    /// 文件保存路径
+    pub fn save_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.save_path = path.into();
+        self
+    }
+
+    /// 图片质量级别，如 `full`、`large`、`medium`
+    pub fn representation(mut self, representation: impl Into<String>) -> Self {
+        self.options.representation = representation.into();
+        self
+    }
+
+    /// 文件名模板，语法见 [`FilenameTemplate`]
+    pub fn filename_template(mut self, template: impl Into<String>) -> Self {
+        self.options.filename_template = template.into();
+        self
+    }
+
+    /// 并发下载任务数
+    pub fn concurrency(mut self, concurrency: u32) -> Self {
+        self.options.concurrency = concurrency;
+        self
+    }
+
+    /// 同时抓取搜索页的任务数，游标翻页时只能顺序抓取，此项不生效
+    pub fn page_workers(mut self, page_workers: u32) -> Self {
+        self.options.page_workers = page_workers;
+        self
+    }
+
+    /// 页面抓取连续失败上限
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.options.max_failures = max_failures;
+        self
+    }
+
+    /// 搜索请求中 `limit` 的计数方式
+    pub fn limit_mode(mut self, mode: LimitMode) -> Self {
+        self.options.limit_mode = mode;
+        self
+    }
+
+    /// 分区数，大于 1 时按 `partition_by` 字段把搜索切成互不重叠的范围并行抓取
+    pub fn partitions(mut self, partitions: u32) -> Self {
+        self.options.partitions = partitions;
+        self
+    }
+
+    /// 分区依据的字段
+    pub fn partition_by(mut self, key: PartitionKey) -> Self {
+        self.options.partition_by = key;
+        self
+    }
+
+    /// 是否为每张图片写入元数据 sidecar
+    pub fn sidecar(mut self, enabled: bool) -> Self {
+        self.options.sidecar = enabled;
+        self
+    }
+
+    /// 是否用站点提供的 SHA-512 校验下载结果
+    pub fn verify(mut self, enabled: bool) -> Self {
+        self.options.verify = enabled;
+        self
+    }
+
+    /// 是否增量同步：只抓取 ID 大于上次同步位置的图片，
+    /// 运行完整（没有失败、没有被 `limit` 截断）时把位置推进到本次见到的最大 ID
+    pub fn sync(mut self, enabled: bool) -> Self {
+        self.options.sync = enabled;
+        self
+    }
+
+    /// 是否无视已有的下载记录，从目录扫描重建状态数据库
+    pub fn rebuild_state(mut self, enabled: bool) -> Self {
+        self.options.rebuild_state = enabled;
+        self
+    }
+
+    /// 订阅下载事件，可多次调用添加多个观察者
+    pub fn observer(mut self, observer: impl DownloadObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// 用于优雅退出的取消令牌
+    ///
+    /// 取消后不再翻页、不再开始新的下载，进行中的下载完成后 [`Downloader::run`] 返回；
+    /// 未完成的文件只会留下可续传的临时文件
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// 一次性替换全部选项
+    pub fn options(mut self, options: DownloadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// 创建保存目录、解析文件名模板并打开状态数据库
+    ///
+    /// 还没有为当前站点扫描过（如首次使用）或要求重建时，扫描已有文件重建记录
+    pub async fn build(self) -> Result<Downloader> {
+        let mut search = self.search;
+        search.validate()?;
+        // 随机排序的种子在整个运行中保持不变
+        search.ensure_seed();
+        let options = self.options;
+        if options.concurrency == 0 {
+            return Err(FerrumenaError::Config(
+                "并发下载任务数必须大于 0".to_string(),
+            ));
+        }
+        if options.page_workers == 0 {
+            return Err(FerrumenaError::Config("翻页任务数必须大于 0".to_string()));
+        }
+
         // 递归路径创建
-        let save_path = client.config.save_path.clone();
-        tokio::fs::create_dir_all(&save_path).await?;
-
-        // 扫描目录获取已有 ID
-        let existing_ids = Self::scan_existing_files(&save_path).await;
-        Ok(Self {
-            client: Arc::new(client),
-            args,
+        tokio::fs::create_dir_all(&options.save_path).await?;
+
+        // 解析文件名模板
+        let template = FilenameTemplate::parse(&options.filename_template)?;
+
+        // 打开状态数据库，必要时从目录扫描重建
+        let state = StateDb::open(&options.save_path, &self.client.config.base_url)?;
+        if options.rebuild_state || state.needs_scan()? {
+            let records =
+                Downloader::scan_existing_files(&options.save_path, &template, &self.observers)
+                    .await;
+            let records = state.rebuild(&records)?;
+            self.observers.emit(DownloadEvent::StateRebuilt { records });
+        }
+        let existing_ids = state.ids()?;
+
+        // 增量同步：从上次记录的位置之后开始抓取
+        if options.sync {
+            let filter_id = search.filter_id.unwrap_or(self.client.config.filter_id);
+            search.since_id = state.sync_position(&search.query, filter_id)?;
+            self.observers.emit(DownloadEvent::SyncStarted {
+                since_id: search.since_id,
+            });
+        }
+
+        Ok(Downloader {
+            client: self.client,
+            search,
+            options: Arc::new(options),
             existing_ids: Arc::new(existing_ids),
+            state: Arc::new(state),
+            template: Arc::new(template),
+            observers: Arc::new(self.observers),
+            shutdown: self.shutdown,
         })
     }
+}
 
-    /// 扫描文件夹，提取已存在的图片 ID
-    async fn scan_existing_files(save_path: &Path) -> HashSet<u32> {
-        let mut entries = match tokio::fs::read_dir(save_path).await {
-            Ok(en) => en,
-            Err(err) => {
-                println!("❓  读取路径 {} 出错：{}", save_path.display(), err);
-                return HashSet::new();
-            }
-        };
+/// 搜索并下载图片的引擎
+///
+/// `page_workers` 个翻页任务按页生产下载任务，`concurrency` 个 Worker 并发消费
+pub struct Downloader {
+    client: Arc<PhilomenaClient>,
+    search: SearchRequest,
+    // 运行选项
+    options: Arc<DownloadOptions>,
+    // 运行开始时已下载的 ID，用于去重
+    existing_ids: Arc<HashSet<u32>>,
+    // 下载状态数据库
+    state: Arc<StateDb>,
+    // 文件名模板
+    template: Arc<FilenameTemplate>,
+    // 事件观察者
+    observers: Arc<Observers>,
+    // 取消后停止翻页和下载
+    shutdown: CancellationToken,
+}
+
+impl Downloader {
+    /// 创建构建器，选项默认取自客户端的配置
+    ///
+    /// `client` 可以是 [`PhilomenaClient`] 或 `Arc<PhilomenaClient>`，
+    /// 后者便于多个下载器共用同一个客户端
+    pub fn builder(
+        client: impl Into<Arc<PhilomenaClient>>,
+        search: SearchRequest,
+    ) -> DownloaderBuilder {
+        let client = client.into();
+        let options = DownloadOptions::from_config(&client.config);
+        DownloaderBuilder {
+            client,
+            search,
+            options,
+            observers: Observers::default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// 本次运行的搜索参数（随机排序时已确定种子）
+    pub fn search(&self) -> &SearchRequest {
+        &self.search
+    }
 
-        let mut ids = HashSet::new();
-        while let Ok(Some(entry)) = entries.next_entry().await {
-            // 只保留文件
-            if let Ok(file_type) = entry.file_type().await {
-                if !file_type.is_file() {
+    /// 本次运行使用的选项
+    pub fn options(&self) -> &DownloadOptions {
+        &self.options
+    }
+
+    /// 运行开始时已下载的图片数量
+    pub fn existing_count(&self) -> usize {
+        self.existing_ids.len()
+    }
+
+    /// 保存目录下的下载状态数据库
+    pub fn state(&self) -> &StateDb {
+        &self.state
+    }
+
+    /// 递归扫描文件夹，为已存在的图片生成下载记录
+    ///
+    /// 先按当前模板反推 ID，不匹配时再兼容旧的 `{id}.{ext}` 命名；
+    /// 有同名 sidecar 时从中取哈希、representation 和下载时间
+    async fn scan_existing_files(
+        save_path: &Path,
+        template: &FilenameTemplate,
+        observers: &Observers,
+    ) -> Vec<DownloadRecord> {
+        let mut records = Vec::new();
+        let mut dirs = vec![save_path.to_path_buf()];
+
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(en) => en,
+                Err(error) => {
+                    observers.emit(DownloadEvent::ScanFailed {
+                        dir: &dir,
+                        error: &error,
+                    });
                     continue;
                 }
-            }
+            };
 
-            // 获取文件名 -> 获取主名 -> 转换字符串 -> 解析数字
-            if let Some(file_stem) = entry.path().file_stem() {
-                if let Some(id_str) = file_stem.to_str() {
-                    if let Ok(id) = id_str.parse::<u32>() {
-                        ids.insert(id);
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+
+                // 子目录入栈，其余非文件跳过
+                match entry.file_type().await {
+                    Ok(ft) if ft.is_dir() => {
+                        dirs.push(path);
+                        continue;
                     }
+                    Ok(ft) if !ft.is_file() => continue,
+                    _ => {}
+                }
+
+                // 跳过元数据 sidecar、未完成的临时文件和状态数据库本身
+                if path.extension().is_some_and(|ext| {
+                    ext == SIDECAR_EXT || ext == PART_EXT || ext == PART_META_EXT
+                }) || entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(STATE_DB_NAME)
+                {
+                    continue;
+                }
+
+                let rel_path = slash_path(path.strip_prefix(save_path).unwrap_or(&path));
+
+                // 获取文件名 -> 获取主名 -> 转换字符串 -> 解析数字
+                let legacy_id = || {
+                    path.file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .and_then(|id_str| id_str.parse::<u32>().ok())
+                };
+                let Some(id) = template.extract_id(&rel_path).or_else(legacy_id) else {
+                    continue;
+                };
+
+                // 旧版本可能把错误页保存成了图片，内容不符的不算已下载
+                let ext = path
+                    .extension()
+                    .map(|e| e.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                if let Ok(head) = transfer::read_head(&path).await
+                    && let Err(error) = verify::check_content(id, &ext, &head)
+                {
+                    observers.emit(DownloadEvent::ExistingInvalid {
+                        rel_path: &rel_path,
+                        error: &error,
+                    });
+                    continue;
                 }
+
+                let metadata = entry.metadata().await.ok();
+                let sidecar = match tokio::fs::read(Sidecar::path_for(&path)).await {
+                    Ok(body) => serde_json::from_slice::<Sidecar>(&body).ok(),
+                    Err(_) => None,
+                };
+                let downloaded_at = match &sidecar {
+                    Some(sidecar) => sidecar.downloaded_at,
+                    None => metadata
+                        .as_ref()
+                        .and_then(|m| m.modified().ok())
+                        .map(DateTime::<Utc>::from)
+                        .unwrap_or_else(Utc::now),
+                };
+                records.push(DownloadRecord {
+                    image_id: id,
+                    path: rel_path,
+                    sha512: sidecar.as_ref().map(|s| s.sha512.clone()),
+                    size: metadata.map(|m| m.len()).unwrap_or_default(),
+                    representation: sidecar.map(|s| s.representation),
+                    downloaded_at,
+                });
             }
         }
-        ids
+        records
+    }
+
+    /// 写入元数据 sidecar
+    async fn write_sidecar(
+        client: &PhilomenaClient,
+        task: &DownloadTask,
+        file_path: &Path,
+        saved: &SavedFile,
+        random_seed: Option<u32>,
+    ) -> Result<()> {
+        let sidecar = Sidecar {
+            schema_version: SIDECAR_VERSION,
+            site: client.config.base_url.clone(),
+            representation: task.representation.clone(),
+            download_url: task.url.clone(),
+            file_name: file_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            downloaded_at: Utc::now(),
+            sha512: saved.sha512.clone(),
+            verification: saved.verification,
+            random_seed,
+            image: task.image.clone(),
+        };
+        sidecar.write(file_path).await
     }
 
-    pub async fn run(self) -> Result<()> {
+    /// 执行搜索与下载，返回本次运行的统计结果
+    ///
+    /// 页面遇到致命错误（Cloudflare、需要登录等）时返回该错误
+    pub async fn run(self) -> Result<RunSummary> {
+        let started = Instant::now();
+
         // 确定抓取范围
-        let first_page = self.client.fetch_page(1, &self.args).await?;
-        let total_images = first_page.total.ok_or_else(|| {
-            crate::error::FerrumenaError::Logic("第一页未获取到总数信息".to_string())
-        })?;
+        let first_page = self.client.fetch_page(1, &self.search).await?;
+        let total_images = first_page
+            .total
+            .ok_or_else(|| FerrumenaError::Logic("第一页未获取到总数信息".to_string()))?;
 
         // 计算实际需要抓取的图片总数
-        let target_count = match self.args.limit {
+        let target_count = match self.search.limit {
             Some(l) => u32::min(l, total_images),
             None => total_images,
         };
 
+        // 没有可抓取的图片，不必启动任何任务
+        if target_count == 0 {
+            self.observers.emit(DownloadEvent::RunStarted {
+                total: total_images,
+                planned: 0,
+                pages: 0,
+                seed: self.search.random_seed(),
+            });
+            if self.options.sync {
+                self.observers.emit(DownloadEvent::SyncFinished {
+                    previous: self.search.since_id,
+                    newest_id: self.search.since_id,
+                    complete: true,
+                });
+            }
+            let summary = RunSummary {
+                seed: self.search.random_seed(),
+                elapsed: started.elapsed(),
+                ..RunSummary::default()
+            };
+            self.observers
+                .emit(DownloadEvent::RunFinished { summary: &summary });
+            return Ok(summary);
+        }
+
+        // 只计新下载时，已存在的图片不占名额，需要一直翻页直到新下载满 N 张
+        let quota = match (self.options.limit_mode, self.search.limit) {
+            (LimitMode::New, Some(_)) => Some(Arc::new(Semaphore::new(target_count as usize))),
+            _ => None,
+        };
+        let queue_target = if quota.is_some() {
+            total_images
+        } else {
+            target_count
+        };
+        // 按总数截断时只要排序最前的 N 张：按结果中的位置而不是到达的先后判断。
+        // 各分区之间没有统一的先后顺序，此时不分区
+        let ranked = quota.is_none() && target_count < total_images;
+
+        let per_page = self.search.per_page;
+
+        // 分区时每个分区是一次独立的翻页抓取，共用下载队列
+        let crawls = if self.options.partitions > 1 && !ranked {
+            self.plan_partitions().await?
+        } else {
+            vec![(self.search.clone(), first_page)]
+        };
+
         // 计算总页数
-        let per_page = self.args.per_page;
-        let total_pages = (target_count + per_page - 1) / per_page;
+        let pages_of = |resp: &PageResponse| {
+            resp.total
+                .unwrap_or(total_images)
+                .min(queue_target)
+                .div_ceil(per_page)
+        };
+        let total_pages = crawls.iter().map(|(_, first)| pages_of(first)).sum();
 
-        println!(
-            "ℹ️  计划抓取 {} 张图片，共 {} 页",
-            target_count, total_pages
-        );
+        self.observers.emit(DownloadEvent::RunStarted {
+            total: total_images,
+            planned: target_count,
+            pages: total_pages,
+            seed: self.search.random_seed(),
+        });
+
+        let counters = Arc::new(Counters::default());
 
         // 建立通信管道
         // mpsc 通道：Page Worker 生产图片链接，Image Worker 消费
         let (tx, rx) = mpsc::channel::<DownloadTask>(256);
         let rx = Arc::new(Mutex::new(rx));
+        // 下载名额用完后置位，翻页任务不再入队
+        let closed = Arc::new(AtomicBool::new(false));
+        // 已入队的最大图片 ID，增量同步时作为新的同步位置
+        let newest = Arc::new(AtomicU32::new(0));
+        let queue = Arc::new(TaskQueue {
+            tx,
+            representation: self.options.representation.clone(),
+            observers: Arc::clone(&self.observers),
+            queued: std::sync::Mutex::new(HashSet::new()),
+            target: queue_target,
+            rank_per_page: ranked.then_some(per_page),
+            closed: Arc::clone(&closed),
+            newest: Arc::clone(&newest),
+            shutdown: self.shutdown.clone(),
+        });
 
         // 启动并行任务
         let mut worker_handles = vec![];
 
-        // A. 页面抓取任务
-        let client_c = Arc::clone(&self.client);
-        let args_c = self.args.clone();
-        let tx_c = tx.clone();
-        let max_failures = self.client.config.max_failures;
-        let representation = self.client.config.representation.clone();
-        drop(tx); // 立即 drop 原始 tx，只保留 tx_c
-        let page_handle = tokio::spawn(async move {
-            let mut failure_count: u32 = 0;
-
-            for page in 1..=total_pages {
-                match client_c.fetch_page(page, &args_c).await {
-                    Ok(resp) => {
-                        failure_count = 0; // 成功, 重置计数
-
-                        for img in resp.images {
-                            let url = if let Some(url) =
-                                img.representations.get(representation.as_str()).cloned()
-                            {
-                                url
-                            } else {
-                                let compact_view_url = compact_url_for_log(&img.view_url);
-                                println!(
-                                    "⚠️  图片 ID {} 不存在 representation='{}'，已回退到 view_url: {}",
-                                    img.id, representation, compact_view_url
-                                );
-                                img.view_url.clone()
-                            };
-
-                            let task = DownloadTask {
-                                id: img.id,
-                                url,
-                                file_ext: img.format,
-                            };
-                            let _ = tx_c.send(task).await;
-                        }
-                    }
-                    Err(e) => {
-                        failure_count += 1;
-                        println!(
-                            "⚠️  页面 {} 抓取失败: {:#?} ({}/{})",
-                            page, e, failure_count, max_failures
-                        );
-
-                        if failure_count >= max_failures {
-                            println!("❌  连续失败 {} 次，停止爬取页面 No.{}", max_failures, page);
-                            break;
-                        }
-                    }
-                }
-            }
-            drop(tx_c); // 生产者关闭
-        });
-        worker_handles.push(page_handle);
-
-        // B. 图片下载任务
-        let concurrency = self.client.config.concurrency;
+        // A. 图片下载任务
+        let concurrency = self.options.concurrency;
         let client_c = Arc::clone(&self.client);
 
         for i in 0..concurrency {
             let rx_c = Arc::clone(&rx);
             let existing_ids_c = Arc::clone(&self.existing_ids);
             let client_cc = Arc::clone(&client_c);
+            let template_c = Arc::clone(&self.template);
+            let options_c = Arc::clone(&self.options);
+            let observers_c = Arc::clone(&self.observers);
+            let counters_c = Arc::clone(&counters);
+            let random_seed = self.search.random_seed();
+            let state_c = Arc::clone(&self.state);
+            let quota_c = quota.clone();
+            let shutdown_c = self.shutdown.clone();
+            let closed_c = Arc::clone(&closed);
 
             let handle = tokio::spawn(async move {
                 loop {
@@ -168,44 +594,612 @@ impl Downloader {
                         None => break,
                     };
 
+                    // 已请求退出时取出剩余任务但不再下载
+                    if shutdown_c.is_cancelled() {
+                        continue;
+                    }
+
                     // 1. 检查去重
                     if existing_ids_c.contains(&task.id) {
-                        println!("⏭️  Worker {} 跳过已存在: ID {}", i, task.id);
+                        counters_c.skipped.fetch_add(1, Ordering::Relaxed);
+                        observers_c.emit(DownloadEvent::SkippedExisting {
+                            worker: i,
+                            id: task.id,
+                        });
                         continue;
                     }
 
-                    // 2. 执行下载
-                    let file_name = format!("{}.{}", task.id, task.file_ext);
-                    let file_path = client_cc.config.save_path.join(&file_name);
-
-                    match client_cc.client.get(&task.url).send().await {
-                        Ok(resp) => match resp.bytes().await {
-                            Ok(bytes) => match tokio::fs::write(&file_path, bytes).await {
-                                Ok(_) => println!(
-                                    "💾  Worker {} 下载完成: {} (ID: {})",
-                                    i, file_name, task.id
-                                ),
-                                Err(e) => println!(
-                                    "⚠️  Worker {} 保存文件失败: {} - {:#?}",
-                                    i, file_name, e
-                                ),
-                            },
-                            Err(e) => {
-                                println!("⚠️  Worker {} 读取响应失败: {} - {:#?}", i, file_name, e)
-                            }
+                    // 2. 只计新下载时先占一个名额，名额已关闭说明已下载够 N 张
+                    // 此时继续取出剩余任务但不下载，让翻页任务不会阻塞在已满的通道上
+                    let permit = match &quota_c {
+                        Some(quota) => match Arc::clone(quota).acquire_owned().await {
+                            Ok(permit) => Some(permit),
+                            Err(_) => continue,
                         },
-                        Err(e) => println!("⚠️  Worker {} 下载失败: {} - {:#?}", i, file_name, e),
+                        None => None,
+                    };
+
+                    // 3. 执行下载
+                    let rel_path = template_c.render(&task.image, &task.file_ext);
+                    let file_name = rel_path.display().to_string();
+                    let file_path = options_c.save_path.join(&rel_path);
+                    observers_c.emit(DownloadEvent::DownloadStarted {
+                        worker: i,
+                        id: task.id,
+                        file_name: &file_name,
+                    });
+
+                    let result = match file_path.parent() {
+                        Some(parent) => tokio::fs::create_dir_all(parent)
+                            .await
+                            .map_err(FerrumenaError::from),
+                        None => Ok(()),
+                    };
+
+                    // 暂时性错误（含校验失败、传输中断）按重试策略重下
+                    let what = format!("Worker {} 下载 {}", i, file_name);
+                    let progress = |received, total| {
+                        observers_c.emit(DownloadEvent::DownloadProgress {
+                            worker: i,
+                            id: task.id,
+                            received,
+                            total,
+                        });
+                    };
+                    let result = match result {
+                        Ok(()) => {
+                            client_cc
+                                .retry
+                                .run(
+                                    &what,
+                                    |e| observers_c.emit(e),
+                                    || {
+                                        transfer::download_file(
+                                            &client_cc,
+                                            &task,
+                                            &file_path,
+                                            options_c.verify,
+                                            &progress,
+                                        )
+                                    },
+                                )
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+
+                    match result {
+                        Ok(saved) => {
+                            let downloaded =
+                                counters_c.downloaded.fetch_add(1, Ordering::Relaxed) + 1;
+                            // 成功下载消耗名额，失败时名额随 permit 释放，由后面的图片补上
+                            if let Some(permit) = permit {
+                                permit.forget();
+                                if let Some(quota) = &quota_c
+                                    && downloaded >= target_count
+                                {
+                                    quota.close();
+                                    closed_c.store(true, Ordering::Relaxed);
+                                    observers_c.emit(DownloadEvent::LimitReached { downloaded });
+                                }
+                            }
+                            observers_c.emit(DownloadEvent::DownloadFinished {
+                                worker: i,
+                                id: task.id,
+                                file_name: &file_name,
+                                path: &file_path,
+                                verification: saved.verification,
+                            });
+                            let record = DownloadRecord {
+                                image_id: task.id,
+                                path: slash_path(&rel_path),
+                                sha512: Some(saved.sha512.clone()),
+                                size: saved.size,
+                                representation: Some(task.representation.clone()),
+                                downloaded_at: Utc::now(),
+                            };
+                            if let Err(error) = state_c.record(&record) {
+                                observers_c.emit(DownloadEvent::StateFailed {
+                                    worker: i,
+                                    id: task.id,
+                                    error: &error,
+                                });
+                            }
+                            // sidecar 写入失败不影响图片本身
+                            if options_c.sidecar
+                                && let Err(error) = Self::write_sidecar(
+                                    &client_cc,
+                                    &task,
+                                    &file_path,
+                                    &saved,
+                                    random_seed,
+                                )
+                                .await
+                            {
+                                observers_c.emit(DownloadEvent::SidecarFailed {
+                                    worker: i,
+                                    id: task.id,
+                                    error: &error,
+                                });
+                            }
+                        }
+                        Err(error) => {
+                            counters_c.failed.fetch_add(1, Ordering::Relaxed);
+                            // 图片已被删除（404/410）时重试也不会成功，不阻止推进同步位置
+                            if !matches!(error, FerrumenaError::NotFound { .. }) {
+                                counters_c.unresolved.fetch_add(1, Ordering::Relaxed);
+                            }
+                            observers_c.emit(DownloadEvent::DownloadFailed {
+                                worker: i,
+                                id: task.id,
+                                file_name: &file_name,
+                                error: &error,
+                            });
+                        }
                     }
                 }
             });
             worker_handles.push(handle);
         }
 
+        // B. 翻页任务：每次抓取的第一页已取得，其余页按翻页方式抓取
+        let mut page_handles = vec![];
+        for (search, first_page) in crawls {
+            let pages = pages_of(&first_page);
+            match self.search.pagination {
+                PaginationMode::Page => {
+                    page_handles.extend(self.spawn_page_workers(search, pages, &queue, &counters))
+                }
+                PaginationMode::Cursor => page_handles.push(self.spawn_cursor_pages(
+                    search,
+                    &first_page,
+                    pages,
+                    &queue,
+                    &counters,
+                )),
+            }
+            // 第一页的结果直接入队
+            queue.push_page(first_page, 1).await;
+        }
+        // 释放这里持有的队列，翻页任务全部结束时通道关闭
+        drop(queue);
+
         // 等待所有任务完成
         for h in worker_handles {
             let _ = h.await;
         }
 
-        Ok(())
+        // 只有覆盖了全部新结果的运行才推进同步位置，否则下次会漏掉没抓到的图片
+        if self.options.sync {
+            let complete = queue_target >= total_images
+                && !closed.load(Ordering::Relaxed)
+                && !self.shutdown.is_cancelled()
+                && counters.unresolved.load(Ordering::Relaxed) == 0
+                && counters.failed_pages.load(Ordering::Relaxed) == 0;
+            let previous = self.search.since_id;
+            let seen = newest.load(Ordering::Relaxed);
+            let newest_id = if complete && seen > previous.unwrap_or(0) {
+                let filter_id = self
+                    .search
+                    .filter_id
+                    .unwrap_or(self.client.config.filter_id);
+                self.state
+                    .set_sync_position(&self.search.query, filter_id, seen)?;
+                Some(seen)
+            } else {
+                previous
+            };
+            self.observers.emit(DownloadEvent::SyncFinished {
+                previous,
+                newest_id,
+                complete,
+            });
+        }
+
+        let summary = RunSummary {
+            planned: target_count,
+            downloaded: counters.downloaded.load(Ordering::Relaxed),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+            failed_pages: counters.failed_pages.load(Ordering::Relaxed),
+            seed: self.search.random_seed(),
+            elapsed: started.elapsed(),
+        };
+        self.observers
+            .emit(DownloadEvent::RunFinished { summary: &summary });
+
+        // 翻页任务因致命错误退出时，把第一个错误交给调用方
+        for h in page_handles {
+            match h.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(e),
+                Err(e) => {
+                    return Err(FerrumenaError::Logic(format!(
+                        "页面抓取任务异常退出: {}",
+                        e
+                    )));
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// 按页码翻页：多个翻页任务按共享页码领取，连续失败次数合计
+    fn spawn_page_workers(
+        &self,
+        search: SearchRequest,
+        total_pages: u32,
+        queue: &Arc<TaskQueue>,
+        counters: &Arc<Counters>,
+    ) -> Vec<JoinHandle<Result<()>>> {
+        let pages = Arc::new(SharedPages {
+            next: AtomicU32::new(2),
+            last: total_pages,
+            consecutive_failures: AtomicU32::new(0),
+            stopped: AtomicBool::new(false),
+        });
+        let mut page_handles = vec![];
+        for _ in 0..self.options.page_workers {
+            let client_c = Arc::clone(&self.client);
+            let search_c = search.clone();
+            let queue_c = Arc::clone(queue);
+            let max_failures = self.options.max_failures;
+            let observers_c = Arc::clone(&self.observers);
+            let counters_c = Arc::clone(counters);
+            let pages_c = Arc::clone(&pages);
+
+            let handle = tokio::spawn(async move {
+                while !pages_c.stopped.load(Ordering::Relaxed) && !queue_c.is_closed() {
+                    let page = pages_c.next.fetch_add(1, Ordering::Relaxed);
+                    if page > pages_c.last {
+                        break;
+                    }
+
+                    match client_c.fetch_page(page, &search_c).await {
+                        Ok(resp) => {
+                            // 任意一页成功都重置连续失败计数
+                            pages_c.consecutive_failures.store(0, Ordering::Relaxed);
+                            // 已达到目标数量时不再领取新页
+                            if !queue_c.push_page(resp, page).await {
+                                pages_c.stopped.store(true, Ordering::Relaxed);
+                            }
+                        }
+                        Err(e) => {
+                            counters_c.failed_pages.fetch_add(1, Ordering::Relaxed);
+                            let consecutive =
+                                pages_c.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                            // Cloudflare、需要登录等错误继续请求也没用，直接终止
+                            let fatal = e.is_fatal();
+                            observers_c.emit(DownloadEvent::PageFailed {
+                                page,
+                                error: &e,
+                                consecutive,
+                                max_failures,
+                                fatal,
+                            });
+
+                            if fatal {
+                                pages_c.stopped.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                            if consecutive >= max_failures {
+                                pages_c.stopped.store(true, Ordering::Relaxed);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            });
+            page_handles.push(handle);
+        }
+        page_handles
+    }
+
+    /// 游标翻页：单个任务按顺序抓取，每页的条件由上一页最后一张图片决定
+    ///
+    /// 失败的页不能跳过（下一页的条件依赖它），连续失败时重试同一页，达到上限后停止
+    fn spawn_cursor_pages(
+        &self,
+        search: SearchRequest,
+        first_page: &PageResponse,
+        total_pages: u32,
+        queue: &Arc<TaskQueue>,
+        counters: &Arc<Counters>,
+    ) -> JoinHandle<Result<()>> {
+        let per_page = self.search.per_page as usize;
+        let next_request = move |req: &SearchRequest,
+                                 len: usize,
+                                 last: Option<&ImageItem>|
+              -> Result<Option<SearchRequest>> {
+            // 不满一页说明已经是最后一页
+            if len < per_page {
+                return Ok(None);
+            }
+            last.map(|last| req.after(last)).transpose()
+        };
+        // 第一页的条件在任务内计算，出错时与后续页一样由任务返回，
+        // 此前已启动的下载任务照常结束
+        let first_len = first_page.images.len();
+        let first_last = first_page.images.last().cloned();
+
+        let client_c = Arc::clone(&self.client);
+        let queue_c = Arc::clone(queue);
+        let max_failures = self.options.max_failures;
+        let observers_c = Arc::clone(&self.observers);
+        let counters_c = Arc::clone(counters);
+
+        tokio::spawn(async move {
+            let mut next = next_request(&search, first_len, first_last.as_ref())?;
+            let mut page = 2;
+            let mut failure_count: u32 = 0;
+
+            while page <= total_pages
+                && !queue_c.is_closed()
+                && let Some(req) = next.take()
+            {
+                // 游标翻页始终请求第一页
+                match client_c.fetch_page(1, &req).await {
+                    Ok(resp) => {
+                        failure_count = 0;
+                        next = next_request(&req, resp.images.len(), resp.images.last())?;
+                        if !queue_c.push_page(resp, page).await {
+                            break;
+                        }
+                        page += 1;
+                    }
+                    Err(e) => {
+                        counters_c.failed_pages.fetch_add(1, Ordering::Relaxed);
+                        failure_count += 1;
+                        let fatal = e.is_fatal();
+                        observers_c.emit(DownloadEvent::PageFailed {
+                            page,
+                            error: &e,
+                            consecutive: failure_count,
+                            max_failures,
+                            fatal,
+                        });
+
+                        if fatal {
+                            return Err(e);
+                        }
+                        if failure_count >= max_failures {
+                            break;
+                        }
+                        next = Some(req);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// 规划分区并取得每个分区的第一页
+    async fn plan_partitions(&self) -> Result<Vec<(SearchRequest, PageResponse)>> {
+        let partitions = partition::plan(
+            &self.client,
+            &self.search,
+            self.options.partition_by,
+            self.options.partitions,
+        )
+        .await?;
+
+        let mut crawls = Vec::with_capacity(partitions.len());
+        for (i, part) in partitions.iter().enumerate() {
+            self.observers.emit(DownloadEvent::PartitionPlanned {
+                index: i as u32 + 1,
+                count: partitions.len() as u32,
+                constraint: &part.constraint,
+                total: part.total,
+            });
+            let search = part.apply(&self.search);
+            let first_page = self.client.fetch_page(1, &search).await?;
+            crawls.push((search, first_page));
+        }
+        Ok(crawls)
+    }
+}
+
+/// 所有翻页任务共用的下载队列：转换下载任务、去重并控制总数
+struct TaskQueue {
+    tx: mpsc::Sender<DownloadTask>,
+    representation: String,
+    observers: Arc<Observers>,
+    // 已入队的 ID，分区边界或翻页错位产生的重复只下载一次
+    queued: std::sync::Mutex<HashSet<u32>>,
+    // 最多入队的数量
+    target: u32,
+    // 按结果位置截断时的每页数量：第 page 页第 i 张的位置为 (page-1)*per_page + i，
+    // 位置不小于 target 的不入队
+    rank_per_page: Option<u32>,
+    // 只计新下载时，下载满 N 张后置位
+    closed: Arc<AtomicBool>,
+    // 已入队的最大图片 ID
+    newest: Arc<AtomicU32>,
+    // 请求退出后不再入队
+    shutdown: CancellationToken,
+}
+
+impl TaskQueue {
+    /// 把一页搜索结果转换为下载任务并送入队列
+    ///
+    /// 返回 false 表示已达到目标数量，翻页任务应停止
+    async fn push_page(&self, resp: PageResponse, page: u32) -> bool {
+        self.observers.emit(DownloadEvent::PageFetched {
+            page,
+            images: resp.images.len(),
+        });
+
+        for (i, img) in resp.images.into_iter().enumerate() {
+            if self.is_closed() {
+                return false;
+            }
+            {
+                let mut queued = self.queued.lock().unwrap();
+                let over = match self.rank_per_page {
+                    // 翻页任务并发时页面到达的先后不定，按位置判断才能保证是前 N 张
+                    Some(per_page) => {
+                        (page as u64 - 1) * per_page as u64 + i as u64 >= self.target as u64
+                    }
+                    None => queued.len() as u32 >= self.target,
+                };
+                if over {
+                    return false;
+                }
+                if !queued.insert(img.id) {
+                    continue;
+                }
+            }
+            self.newest.fetch_max(img.id, Ordering::Relaxed);
+
+            let representation = self.representation.as_str();
+            let (url, used_rep) =
+                if let Some(url) = img.representations.get(representation).cloned() {
+                    (url, representation.to_string())
+                } else {
+                    self.observers.emit(DownloadEvent::RepresentationFallback {
+                        id: img.id,
+                        representation,
+                        view_url: &img.view_url,
+                    });
+                    (img.view_url.clone(), "view".to_string())
+                };
+
+            // 保存的扩展名以实际下载的链接为准，缩略图与原图的格式可能不同
+            let file_ext = ext_from_url(&url).unwrap_or_else(|| img.format.clone());
+            let task = DownloadTask {
+                id: img.id,
+                url,
+                file_ext,
+                representation: used_rep,
+                image: img,
+            };
+            self.observers
+                .emit(DownloadEvent::TaskQueued { id: task.id, page });
+            let _ = self.tx.send(task).await;
+        }
+        if self.rank_per_page.is_some() {
+            return !self.is_closed();
+        }
+        !self.is_closed() && (self.queued.lock().unwrap().len() as u32) < self.target
+    }
+
+    /// 已下载够数量或已请求退出，不再入队
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed) || self.shutdown.is_cancelled()
+    }
+}
+
+/// 按页码翻页时各翻页任务共享的进度
+struct SharedPages {
+    // 下一个待领取的页码
+    next: AtomicU32,
+    // 最后一页
+    last: u32,
+    // 所有翻页任务合计的连续失败次数
+    consecutive_failures: AtomicU32,
+    // 致命错误或连续失败达到上限后置位，其余任务不再领取新页
+    stopped: AtomicBool,
+}
+
+/// 相对路径统一使用 `/` 分隔，与平台无关
+fn slash_path(rel_path: &Path) -> String {
+    rel_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// 运行过程中的计数，各任务共享
+#[derive(Default)]
+struct Counters {
+    downloaded: AtomicU32,
+    skipped: AtomicU32,
+    failed: AtomicU32,
+    // 下载失败且下次可能成功的数量（不含 404/410），存在时不推进同步位置
+    unresolved: AtomicU32,
+    failed_pages: AtomicU32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(ids: std::ops::Range<u32>) -> PageResponse {
+        let images = ids
+            .map(|id| {
+                serde_json::from_value(serde_json::json!({
+                    "id": id,
+                    "representations": { "full": format!("https://example.org/{}.png", id) },
+                    "view_url": "",
+                    "format": "png",
+                    "tags": [],
+                    "tag_ids": [],
+                    "source_urls": [],
+                }))
+                .unwrap()
+            })
+            .collect();
+        PageResponse {
+            total: None,
+            images,
+        }
+    }
+
+    fn queue(target: u32, rank_per_page: Option<u32>) -> (TaskQueue, mpsc::Receiver<DownloadTask>) {
+        let (tx, rx) = mpsc::channel(1024);
+        let queue = TaskQueue {
+            tx,
+            representation: "full".to_string(),
+            observers: Arc::new(Observers::default()),
+            queued: std::sync::Mutex::new(HashSet::new()),
+            target,
+            rank_per_page,
+            closed: Arc::new(AtomicBool::new(false)),
+            newest: Arc::new(AtomicU32::new(0)),
+            shutdown: CancellationToken::new(),
+        };
+        (queue, rx)
+    }
+
+    fn drain(mut rx: mpsc::Receiver<DownloadTask>) -> Vec<u32> {
+        let mut ids = Vec::new();
+        while let Ok(task) = rx.try_recv() {
+            ids.push(task.id);
+        }
+        ids.sort_unstable();
+        ids
+    }
+
+    #[tokio::test]
+    async fn ranked_limit_keeps_first_results_when_pages_arrive_out_of_order() {
+        // -l 120 -p 50：第 3 页先于第 2 页返回
+        let (queue, rx) = queue(120, Some(50));
+        assert!(queue.push_page(page(0..50), 1).await);
+        assert!(!queue.push_page(page(100..150), 3).await);
+        assert!(queue.push_page(page(50..100), 2).await);
+        drop(queue);
+        assert_eq!(drain(rx), (0..120).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn unranked_limit_counts_queued_images() {
+        let (queue, rx) = queue(60, None);
+        assert!(queue.push_page(page(0..50), 1).await);
+        // 重复的 ID 不占名额
+        assert!(queue.push_page(page(40..50), 2).await);
+        assert!(!queue.push_page(page(50..100), 3).await);
+        drop(queue);
+        assert_eq!(drain(rx), (0..60).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn closed_queue_accepts_nothing() {
+        let (queue, rx) = queue(100, None);
+        queue.closed.store(true, Ordering::Relaxed);
+        assert!(!queue.push_page(page(0..10), 1).await);
+        drop(queue);
+        assert!(drain(rx).is_empty());
     }
 }
@@ -0,0 +1,250 @@
+use crate::api::client::PhilomenaClient;
+use crate::api::models::DownloadTask;
+use crate::error::{FerrumenaError, Result};
+use crate::resume::{PartMeta, parse_content_range, part_path_for};
+use crate::verify::{self, Verification};
+use reqwest::{StatusCode, header};
+use sha2::{Digest, Sha512};
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// 进度回调，参数为 (已写入字节数, 文件总长度)
+pub(super) type Progress<'a> = dyn Fn(u64, Option<u64>) + Send + Sync + 'a;
+
+/// 已写入磁盘的文件信息
+pub(super) struct SavedFile {
+    pub sha512: String,
+    pub size: u64,
+    pub verification: Verification,
+}
+
+/// 读取文件开头用于判断格式
+pub(super) async fn read_head(path: &Path) -> Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut head = vec![0u8; verify::SNIFF_LEN];
+    let mut filled = 0;
+    while filled < head.len() {
+        let n = file.read(&mut head[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    head.truncate(filled);
+    Ok(head)
+}
+
+/// 下载单个文件
+///
+/// 响应体逐块写入 `.part` 临时文件并同时计算哈希，
+/// 校验通过后才重命名为最终文件名。传输中断时保留临时文件供下次续传，
+/// 内容格式不符或哈希校验失败时连同续传信息一起删除
+pub(super) async fn download_file(
+    client: &PhilomenaClient,
+    task: &DownloadTask,
+    file_path: &Path,
+    verify_hash: bool,
+    progress: &Progress<'_>,
+) -> Result<SavedFile> {
+    let part_path = part_path_for(file_path);
+
+    let saved = match stream_to_part(client, task, &part_path, verify_hash, progress).await {
+        Ok(saved) => saved,
+        Err(e) => {
+            if matches!(
+                e,
+                FerrumenaError::HashMismatch { .. } | FerrumenaError::ContentMismatch { .. }
+            ) {
+                PartMeta::discard(&part_path).await;
+            }
+            return Err(e);
+        }
+    };
+
+    tokio::fs::rename(&part_path, file_path).await?;
+    PartMeta::discard(&part_path).await;
+    Ok(saved)
+}
+
+/// 流式写入临时文件并校验，已有可续传的临时文件时使用 Range 请求
+async fn stream_to_part(
+    client: &PhilomenaClient,
+    task: &DownloadTask,
+    part_path: &Path,
+    verify_hash: bool,
+    progress: &Progress<'_>,
+) -> Result<SavedFile> {
+    let host = reqwest::Url::parse(&task.url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let (mut resp, offset, meta) = open_transfer(client, task, part_path, &host).await?;
+
+    if let Some(content_type) = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        && content_type.starts_with("text/html")
+    {
+        return Err(FerrumenaError::ContentMismatch {
+            id: task.id,
+            expected: task.file_ext.clone(),
+            actual: content_type.to_string(),
+        });
+    }
+
+    // 从头下载时先读出文件头，确认与预期格式一致再落盘，
+    // 防止错误页、Cloudflare 验证页被当成图片保存
+    let mut head = Vec::new();
+    if offset == 0 {
+        while head.len() < verify::SNIFF_LEN {
+            match resp.chunk().await? {
+                Some(chunk) => {
+                    client.image_limiter.consume_bytes(&host, chunk.len()).await;
+                    head.extend_from_slice(&chunk);
+                }
+                None => break,
+            }
+        }
+        verify::check_content(task.id, &task.file_ext, &head)?;
+    }
+
+    // 续传时先把已有内容计入哈希
+    let mut hasher = Sha512::new();
+    let mut file = if offset > 0 {
+        let mut existing = tokio::fs::File::open(part_path).await?;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = existing.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(part_path)
+            .await?
+    } else {
+        tokio::fs::File::create(part_path).await?
+    };
+
+    hasher.update(&head);
+    file.write_all(&head).await?;
+
+    let mut written = offset + head.len() as u64;
+    progress(written, meta.total_len);
+    while let Some(chunk) = resp.chunk().await? {
+        client.image_limiter.consume_bytes(&host, chunk.len()).await;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        progress(written, meta.total_len);
+    }
+    file.flush().await?;
+    drop(file);
+
+    // 长度不符说明连接被提前关闭，保留临时文件等待续传
+    if let Some(total) = meta.total_len
+        && written != total
+    {
+        return Err(FerrumenaError::Incomplete {
+            id: task.id,
+            received: written,
+            expected: total,
+        });
+    }
+
+    let sha512 = hex::encode(hasher.finalize());
+    let verification = if verify_hash {
+        verify::check(&task.image, &task.representation, &sha512)?
+    } else {
+        Verification::Disabled
+    };
+
+    Ok(SavedFile {
+        sha512,
+        size: written,
+        verification,
+    })
+}
+
+/// 发起下载请求，返回 (响应, 续传起点, 续传信息)
+///
+/// 服务器不支持 Range、文件已变化或 Content-Range 对不上时从头开始
+async fn open_transfer(
+    client: &PhilomenaClient,
+    task: &DownloadTask,
+    part_path: &Path,
+    host: &str,
+) -> Result<(reqwest::Response, u64, PartMeta)> {
+    let existing_len = tokio::fs::metadata(part_path)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let resumable = match PartMeta::load(part_path).await {
+        Some(meta) if existing_len > 0 && meta.url == task.url => {
+            meta.validator().map(|v| (meta.clone(), v.to_string()))
+        }
+        _ => None,
+    };
+
+    // 按主机申请图片请求配额，该主机限流期间所有 Worker 一起暂停
+    client.image_limiter.until_request_ready(host).await;
+
+    let resumable_attempted = resumable.is_some();
+    if let Some((meta, validator)) = resumable {
+        let req = client
+            .client
+            .get(&task.url)
+            .header(header::RANGE, format!("bytes={}-", existing_len))
+            .header(header::IF_RANGE, validator);
+
+        // 416 说明临时文件已不可用，走下面的重新下载；其余错误直接返回
+        let resp = match client.send_image(req, host).await {
+            Ok(resp) => Some(resp),
+            Err(FerrumenaError::Http { status, .. })
+                if status == StatusCode::RANGE_NOT_SATISFIABLE =>
+            {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        match resp {
+            Some(resp) if resp.status() == StatusCode::PARTIAL_CONTENT => {
+                let range_ok = match parse_content_range(&resp) {
+                    Some((start, total)) => {
+                        start == existing_len
+                            && (total.is_none()
+                                || meta.total_len.is_none()
+                                || total == meta.total_len)
+                    }
+                    None => false,
+                };
+                if range_ok {
+                    return Ok((resp, existing_len, meta));
+                }
+            }
+            Some(resp) => {
+                // If-Range 不满足，服务器直接返回了完整的新文件
+                let meta = PartMeta::from_response(&task.url, &resp);
+                meta.save(part_path).await?;
+                return Ok((resp, 0, meta));
+            }
+            None => {}
+        }
+        // 其余情况（416、Content-Range 不符等）丢弃旧的临时文件重新下载
+    }
+
+    PartMeta::discard(part_path).await;
+    if resumable_attempted {
+        client.image_limiter.until_request_ready(host).await;
+    }
+    let resp = client
+        .send_image(client.client.get(&task.url), host)
+        .await?;
+    let meta = PartMeta::from_response(&task.url, &resp);
+    meta.save(part_path).await?;
+    Ok((resp, 0, meta))
+}
@@ -1,24 +1,30 @@
+use reqwest::StatusCode;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Ferrumena 的核心错误类型
 #[derive(Debug, Error)]
 pub enum FerrumenaError {
     /// 1. 网络层错误
+    ///
     /// 网络断开、DNS 解析失败或连接超时等触发
     #[error("❓  网络传输失败: {0:#?}")]
     Network(#[from] reqwest::Error),
 
     /// 2. 磁盘与 IO 错误
+    ///
     /// 硬盘存在问题、文件夹没权限或保存图片失败时触发
     #[error("❓  文件操作失败: {0:#?}")]
     Io(#[from] std::io::Error),
 
     /// 3. DOM 解析错误
+    ///
     /// 当 Philomena 网页结构发生变化，导致 CSS 选择器失效时触发
     #[error("❓  网页解析失败: 找不到元素 [{selector}] (位置: {location})")]
     DomParse { selector: String, location: String },
 
     /// 4. 业务逻辑错误
+    ///
     /// 例如：图片已被删除、该 ID 不存在、或者触发了 404 等
     #[error("❓  业务逻辑错误: {0}")]
     Logic(String),
@@ -27,10 +33,140 @@ pub enum FerrumenaError {
     #[error("❓  配置错误: {0}")]
     Config(String),
 
-    // /// 6. 其他未知错误
+    /// 6. JSON API 解析错误
+    ///
+    /// 当 `/api/v1/json` 返回的数据无法反序列化时触发
+    #[error("❓  API 响应解析失败: {0}")]
+    ApiParse(#[from] serde_json::Error),
+    /// 7. 完整性校验失败
+    ///
+    /// 下载内容的 SHA-512 与站点提供的哈希不一致（传输被截断、CDN 出错等）
+    #[error("❓  校验失败: 图片 ID {id} 的 SHA-512 不匹配 (期望 {expected}, 实际 {actual})")]
+    HashMismatch {
+        id: u32,
+        expected: String,
+        actual: String,
+    },
+    /// 8. 传输不完整
+    ///
+    /// 连接在文件传完之前被关闭，临时文件会保留以便续传
+    #[error("❓  传输不完整: 图片 ID {id} 只收到 {received}/{expected} 字节")]
+    Incomplete {
+        id: u32,
+        received: u64,
+        expected: u64,
+    },
+    /// 9. 内容与预期格式不符
+    ///
+    /// 服务器返回了 HTML 错误页、Cloudflare 验证页等，而不是图片本身
+    #[error("❓  内容不符: 图片 ID {id} 预期为 {expected}，实际为 {actual}")]
+    ContentMismatch {
+        id: u32,
+        expected: String,
+        actual: String,
+    },
+
+    /// 10. HTTP 状态错误
+    ///
+    /// 服务器返回了其它非 2xx 状态码
+    #[error("❓  HTTP {status}: {url}")]
+    Http { status: StatusCode, url: String },
+
+    /// 11. Cloudflare 防护
+    ///
+    /// 请求被 Cloudflare 验证页拦截，需要配置浏览器的 Cookie / UA
+    #[error("❓  被 Cloudflare 拦截: {url}（请配置浏览器的 Cookie 与 User-Agent）")]
+    Cloudflare { url: String },
+
+    /// 12. 被站点限流
+    ///
+    /// 服务器返回 429，retry_after 取自 `Retry-After` 响应头
+    #[error("❓  被站点限流: {url}{}", retry_after.map(|d| format!("（{}s 后可重试）", d.as_secs())).unwrap_or_default())]
+    RateLimited {
+        url: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// 13. 需要登录
+    ///
+    /// 服务器返回 401 或重定向到登录页（例如 `my:upvotes` 之类的搜索）
+    #[error("❓  需要登录: {url}（请在 Cookie 中提供登录凭证）")]
+    LoginRequired { url: String },
+
+    /// 14. 资源不存在
+    ///
+    /// 404 / 410，图片已被删除或地址错误
+    #[error("❓  资源不存在或已删除: {url}")]
+    NotFound { url: String },
+
+    /// 15. 状态数据库错误
+    ///
+    /// 保存目录下的 `.ferrumena.db` 无法打开或读写
+    #[error("❓  状态数据库错误: {0}")]
+    State(#[from] rusqlite::Error),
+    // /// 16. 其他未知错误
     // #[error("❓  未知错误: {0}")]
     // Unknown(String),
 }
 
+impl FerrumenaError {
+    /// 是否为暂时性错误（超时、连接失败、5xx、429 等），值得等待后重试
+    ///
+    /// 404 / 410 等明确的客户端错误以及解析、配置错误重试也没有意义
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FerrumenaError::Network(e) => {
+                e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+            }
+            FerrumenaError::Http { status, .. } => {
+                status.is_server_error() || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            FerrumenaError::RateLimited { .. }
+            | FerrumenaError::HashMismatch { .. }
+            | FerrumenaError::Incomplete { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// 是否应当立即终止整个任务（继续请求只会得到同样的结果）
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            FerrumenaError::Cloudflare { .. }
+                | FerrumenaError::LoginRequired { .. }
+                | FerrumenaError::Config(_)
+        )
+    }
+
+    /// 进程退出码，便于外部脚本区分失败原因
+    ///
+    /// | 退出码 | 含义 |
+    /// | --- | --- |
+    /// | 1 | 其它错误 |
+    /// | 3 | 网络错误 |
+    /// | 4 | HTTP 状态错误 |
+    /// | 5 | Cloudflare 拦截 |
+    /// | 6 | 被限流 |
+    /// | 7 | 需要登录 |
+    /// | 8 | 资源不存在 |
+    /// | 9 | 文件操作或状态数据库错误 |
+    /// | 10 | 页面 / API 解析错误 |
+    /// | 11 | 配置错误 |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FerrumenaError::Network(_) => 3,
+            FerrumenaError::Http { .. } => 4,
+            FerrumenaError::Cloudflare { .. } => 5,
+            FerrumenaError::RateLimited { .. } => 6,
+            FerrumenaError::LoginRequired { .. } => 7,
+            FerrumenaError::NotFound { .. } => 8,
+            FerrumenaError::Io(_) | FerrumenaError::State(_) => 9,
+            FerrumenaError::DomParse { .. } | FerrumenaError::ApiParse(_) => 10,
+            FerrumenaError::Config(_) => 11,
+            _ => 1,
+        }
+    }
+}
+
 /// 项目统一使用 FerrumenaError 作为错误类型
 pub type Result<T> = std::result::Result<T, FerrumenaError>;
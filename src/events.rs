@@ -0,0 +1,421 @@
+use crate::error::FerrumenaError;
+use crate::utils::compact_url_for_log;
+use crate::verify::Verification;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 下载器运行过程中发生的事件
+///
+/// 事件只借用下载器内部的数据，观察者需要保留时自行复制
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DownloadEvent<'a> {
+    /// 扫描已有文件时读取目录失败
+    ScanFailed {
+        dir: &'a Path,
+        error: &'a std::io::Error,
+    },
+    /// 已有文件内容与扩展名不符（如旧版本保存的错误页），将重新下载
+    ExistingInvalid {
+        rel_path: &'a str,
+        error: &'a FerrumenaError,
+    },
+    /// 从目录扫描重建了状态数据库
+    StateRebuilt { records: usize },
+    /// 增量同步开始，`since_id` 为上次的同步位置，首次同步时为 None
+    SyncStarted { since_id: Option<u32> },
+    /// 分区规划完成，每个分区一条（`index` 从 1 开始）
+    PartitionPlanned {
+        index: u32,
+        count: u32,
+        constraint: &'a str,
+        total: u32,
+    },
+    /// 第一页获取完成，确定了抓取范围
+    RunStarted {
+        total: u32,
+        planned: u32,
+        pages: u32,
+        /// 随机排序使用的种子
+        seed: Option<u32>,
+    },
+    /// 一页搜索结果获取成功
+    PageFetched { page: u32, images: usize },
+    /// 一页搜索结果获取失败（已按重试策略重试）
+    ///
+    /// `fatal` 为 true 或 `consecutive` 达到 `max_failures` 时停止翻页
+    PageFailed {
+        page: u32,
+        error: &'a FerrumenaError,
+        consecutive: u32,
+        max_failures: u32,
+        fatal: bool,
+    },
+    /// 图片没有请求的质量级别，回退到 view_url
+    RepresentationFallback {
+        id: u32,
+        representation: &'a str,
+        view_url: &'a str,
+    },
+    /// 下载任务已加入队列
+    TaskQueued { id: u32, page: u32 },
+    /// 本地已存在，跳过
+    SkippedExisting { worker: u32, id: u32 },
+    /// 开始下载（含断点续传）
+    DownloadStarted {
+        worker: u32,
+        id: u32,
+        file_name: &'a str,
+    },
+    /// 下载进度，`received` 包含续传前已有的字节数
+    DownloadProgress {
+        worker: u32,
+        id: u32,
+        received: u64,
+        total: Option<u64>,
+    },
+    /// 下载完成并通过校验
+    DownloadFinished {
+        worker: u32,
+        id: u32,
+        file_name: &'a str,
+        path: &'a Path,
+        verification: Verification,
+    },
+    /// 下载失败（已按重试策略重试）
+    DownloadFailed {
+        worker: u32,
+        id: u32,
+        file_name: &'a str,
+        error: &'a FerrumenaError,
+    },
+    /// 下载记录写入状态数据库失败，下次运行可能重复下载
+    StateFailed {
+        worker: u32,
+        id: u32,
+        error: &'a FerrumenaError,
+    },
+    /// 只计新下载时已下载满 `limit` 张，停止翻页和下载
+    LimitReached { downloaded: u32 },
+    /// 元数据 sidecar 写入失败，图片本身不受影响
+    SidecarFailed {
+        worker: u32,
+        id: u32,
+        error: &'a FerrumenaError,
+    },
+    /// 增量同步结束，`complete` 为 false 时同步位置保持不变
+    SyncFinished {
+        previous: Option<u32>,
+        newest_id: Option<u32>,
+        complete: bool,
+    },
+    /// 本次运行结束
+    RunFinished { summary: &'a RunSummary },
+    /// 任务文件中的一个任务开始运行（`index` 从 1 开始）
+    JobStarted {
+        index: u32,
+        count: u32,
+        name: &'a str,
+    },
+    /// 任务文件中的一个任务出错，其它任务照常运行
+    JobFailed {
+        name: &'a str,
+        error: &'a FerrumenaError,
+    },
+    /// 常驻模式开始一轮轮询
+    PollStarted { query: &'a str },
+    /// 常驻模式的一轮轮询出错，下一轮照常进行
+    PollFailed {
+        query: &'a str,
+        error: &'a FerrumenaError,
+    },
+    /// 常驻模式等待下一轮轮询
+    PollScheduled { query: &'a str, delay: Duration },
+    /// 暂时性错误，等待 `delay` 后进行第 `attempt + 1` 次尝试
+    Retrying {
+        what: &'a str,
+        attempt: u32,
+        max_attempts: u32,
+        delay: Duration,
+        error: &'a FerrumenaError,
+    },
+    /// 站点要求限流，暂停 `pause` 后以降低的速率继续
+    RateLimited { rps: u32, pause: Duration },
+    /// 一段时间没有再被限流，速率恢复一档
+    RateRecovered { rps: u32 },
+    /// 图片主机要求限流，该主机暂停 `pause` 后以 `rps` 继续；图片请求不限速时 `rps` 为 0
+    HostRateLimited {
+        host: &'a str,
+        rps: u32,
+        pause: Duration,
+    },
+    /// 图片主机的速率恢复一档
+    HostRateRecovered { host: &'a str, rps: u32 },
+    /// auto 模式下 JSON API 不可用，之后改用 HTML 解析（只报告一次）
+    BackendFallback { error: &'a FerrumenaError },
+}
+
+/// 一次运行的统计结果
+#[derive(Debug, Clone, Default)]
+pub struct RunSummary {
+    /// 计划抓取的图片数
+    pub planned: u32,
+    /// 成功下载的图片数
+    pub downloaded: u32,
+    /// 本地已存在而跳过的图片数
+    pub skipped: u32,
+    /// 下载失败的图片数
+    pub failed: u32,
+    /// 获取失败的页数
+    pub failed_pages: u32,
+    /// 随机排序使用的种子，用 `--seed` 传入可复现同样的结果
+    pub seed: Option<u32>,
+    /// 运行耗时
+    pub elapsed: Duration,
+}
+
+/// 下载事件的观察者
+///
+/// 回调在下载器的任务中同步执行，应尽快返回；
+/// 闭包 `Fn(&DownloadEvent)` 也可以直接作为观察者
+pub trait DownloadObserver: Send + Sync {
+    fn on_event(&self, event: &DownloadEvent<'_>);
+}
+
+impl<F> DownloadObserver for F
+where
+    F: Fn(&DownloadEvent<'_>) + Send + Sync,
+{
+    fn on_event(&self, event: &DownloadEvent<'_>) {
+        self(event)
+    }
+}
+
+/// 把事件分发给所有观察者
+#[derive(Clone, Default)]
+pub(crate) struct Observers(Vec<Arc<dyn DownloadObserver>>);
+
+impl Observers {
+    pub fn push(&mut self, observer: Arc<dyn DownloadObserver>) {
+        self.0.push(observer);
+    }
+
+    pub fn emit(&self, event: DownloadEvent<'_>) {
+        for observer in &self.0 {
+            observer.on_event(&event);
+        }
+    }
+}
+
+/// 命令行使用的观察者，按原有格式打印进度
+#[derive(Clone)]
+pub struct ConsoleObserver;
+
+impl DownloadObserver for ConsoleObserver {
+    fn on_event(&self, event: &DownloadEvent<'_>) {
+        match *event {
+            DownloadEvent::ScanFailed { dir, error } => {
+                println!("❓  读取路径 {} 出错：{}", dir.display(), error);
+            }
+            DownloadEvent::ExistingInvalid { rel_path, error } => {
+                println!("⚠️  已有文件 {} 将重新下载: {}", rel_path, error);
+            }
+            DownloadEvent::StateRebuilt { records } => {
+                println!("🗃️  已从现有文件重建下载记录: {} 条", records);
+            }
+            DownloadEvent::SyncStarted { since_id } => match since_id {
+                Some(id) => println!("🔁  增量同步: 只抓取 ID 大于 {} 的图片", id),
+                None => println!("🔁  增量同步: 首次同步，抓取全部结果"),
+            },
+            DownloadEvent::PartitionPlanned {
+                index,
+                count,
+                constraint,
+                total,
+            } => {
+                println!(
+                    "🧩  分区 {}/{}: {} ({} 张)",
+                    index, count, constraint, total
+                );
+            }
+            DownloadEvent::RunStarted {
+                planned,
+                pages,
+                seed,
+                ..
+            } => {
+                println!("ℹ️  计划抓取 {} 张图片，共 {} 页", planned, pages);
+                if let Some(seed) = seed {
+                    println!("🎲  随机种子: {} (使用 --seed {} 可复现)", seed, seed);
+                }
+            }
+            DownloadEvent::PageFailed {
+                page, error, fatal, ..
+            } if fatal => {
+                println!("❌  页面 {} 抓取失败，停止爬取: {}", page, error);
+            }
+            DownloadEvent::PageFailed {
+                page,
+                error,
+                consecutive,
+                max_failures,
+                ..
+            } => {
+                println!(
+                    "⚠️  页面 {} 抓取失败: {:#?} ({}/{})",
+                    page, error, consecutive, max_failures
+                );
+                if consecutive >= max_failures {
+                    println!("❌  连续失败 {} 次，停止爬取页面 No.{}", max_failures, page);
+                }
+            }
+            DownloadEvent::RepresentationFallback {
+                id,
+                representation,
+                view_url,
+            } => {
+                println!(
+                    "⚠️  图片 ID {} 不存在 representation='{}'，已回退到 view_url: {}",
+                    id,
+                    representation,
+                    compact_url_for_log(view_url)
+                );
+            }
+            DownloadEvent::SkippedExisting { worker, id } => {
+                println!("⏭️  Worker {} 跳过已存在: ID {}", worker, id);
+            }
+            DownloadEvent::DownloadFinished {
+                worker,
+                id,
+                file_name,
+                verification,
+                ..
+            } => {
+                println!(
+                    "💾  Worker {} 下载完成: {} (ID: {}, {})",
+                    worker, file_name, id, verification
+                );
+            }
+            DownloadEvent::DownloadFailed {
+                worker,
+                file_name,
+                error,
+                ..
+            } => {
+                println!(
+                    "⚠️  Worker {} 下载失败: {} - {:#?}",
+                    worker, file_name, error
+                );
+            }
+            DownloadEvent::StateFailed { worker, id, error } => {
+                println!(
+                    "⚠️  Worker {} 写入下载记录失败: ID {} - {:#?}",
+                    worker, id, error
+                );
+            }
+            DownloadEvent::LimitReached { downloaded } => {
+                println!("🎯  已新下载 {} 张，达到数量上限", downloaded);
+            }
+            DownloadEvent::SidecarFailed { worker, id, error } => {
+                println!(
+                    "⚠️  Worker {} 写入元数据失败: ID {} - {:#?}",
+                    worker, id, error
+                );
+            }
+            DownloadEvent::SyncFinished {
+                complete: false, ..
+            } => {
+                println!("⚠️  本次运行不完整，同步位置保持不变");
+            }
+            DownloadEvent::SyncFinished {
+                previous,
+                newest_id: Some(id),
+                ..
+            } if previous != Some(id) => {
+                println!("🔁  同步位置已更新到 ID {}", id);
+            }
+            DownloadEvent::SyncFinished { .. } => {
+                println!("🔁  没有新图片");
+            }
+            DownloadEvent::RunFinished { summary } => {
+                println!(
+                    "📊  下载 {} 张，跳过 {} 张，失败 {} 张，失败页 {} 页，耗时 {:.1}s",
+                    summary.downloaded,
+                    summary.skipped,
+                    summary.failed,
+                    summary.failed_pages,
+                    summary.elapsed.as_secs_f64()
+                );
+            }
+            DownloadEvent::JobStarted { index, count, name } => {
+                println!("📦  任务 {}/{}: {}", index, count, name);
+            }
+            DownloadEvent::JobFailed { name, error } => {
+                println!("❌  任务 {} 失败: {}", name, error);
+            }
+            DownloadEvent::PollStarted { query } => {
+                println!("⏰  开始轮询: {}", query);
+            }
+            DownloadEvent::PollFailed { query, error } => {
+                println!("⚠️  轮询 {} 出错，下一轮继续: {}", query, error);
+            }
+            DownloadEvent::PollScheduled { query, delay } => {
+                println!("💤  {} 秒后再次轮询: {}", delay.as_secs(), query);
+            }
+            DownloadEvent::Retrying {
+                what,
+                attempt,
+                max_attempts,
+                delay,
+                error,
+            } => {
+                println!(
+                    "🔁  {} 失败 ({}/{})，{:.1}s 后重试: {}",
+                    what,
+                    attempt,
+                    max_attempts,
+                    delay.as_secs_f64(),
+                    error
+                );
+            }
+            DownloadEvent::RateLimited { rps, pause } => {
+                println!(
+                    "🐢  站点要求限流，暂停 {:.0}s，速率降至 {} 请求/秒",
+                    pause.as_secs_f64(),
+                    rps
+                );
+            }
+            DownloadEvent::RateRecovered { rps } => {
+                println!("🐇  速率恢复至 {} 请求/秒", rps);
+            }
+            DownloadEvent::HostRateLimited {
+                host,
+                rps: 0,
+                pause,
+            } => {
+                println!(
+                    "🐢  图片主机 {} 要求限流，暂停 {:.0}s",
+                    host,
+                    pause.as_secs_f64()
+                );
+            }
+            DownloadEvent::HostRateLimited { host, rps, pause } => {
+                println!(
+                    "🐢  图片主机 {} 要求限流，暂停 {:.0}s，速率降至 {} 请求/秒",
+                    host,
+                    pause.as_secs_f64(),
+                    rps
+                );
+            }
+            DownloadEvent::HostRateRecovered { host, rps } => {
+                println!("🐇  图片主机 {} 速率恢复至 {} 请求/秒", host, rps);
+            }
+            DownloadEvent::BackendFallback { error } => {
+                println!("⚠️  JSON API 不可用，回退到 HTML 解析: {}", error);
+            }
+            // 页面、排队、开始和进度事件过于频繁，命令行不打印
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::api::client::PhilomenaClient;
+use crate::config::FerrumenaConfig;
+use crate::downloader::{DownloadOptions, Downloader};
+use crate::error::{FerrumenaError, Result};
+use crate::events::{DownloadEvent, DownloadObserver, RunSummary};
+use crate::search::{SearchRequest, SortField, SortOrder};
+use crate::utils::site_url;
+use futures::{StreamExt, stream};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// 任务文件：在一个 TOML 文件中描述多个下载任务
+///
+/// ```toml
+/// parallel = 4
+///
+/// [[job]]
+/// name = "example"
+/// query = "artist:example"
+/// output = "artists/example"
+/// sort_field = "score"
+/// limit = 500
+/// ```
+///
+/// 格式见 README「任务文件」一节
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobFile {
+    /// 同时运行的任务数，默认 1（按顺序执行）
+    #[serde(default = "default_parallel")]
+    pub parallel: u32,
+    #[serde(rename = "job", default)]
+    pub jobs: Vec<Job>,
+}
+
+fn default_parallel() -> u32 {
+    1
+}
+
+/// 任务文件中的一个任务，未填写的字段沿用命令行和环境变量的设置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// 任务名称，用于日志，默认为搜索句
+    pub name: Option<String>,
+    /// 搜索句
+    pub query: String,
+    /// 站点地址，如 `https://derpibooru.org/`
+    pub site: Option<String>,
+    pub filter_id: Option<u32>,
+    pub sort_field: Option<SortField>,
+    pub sort_direction: Option<SortOrder>,
+    pub limit: Option<u32>,
+    /// 保存路径，相对路径以任务文件所在目录为基准
+    pub output: Option<PathBuf>,
+    pub filename_template: Option<String>,
+    pub representation: Option<String>,
+    /// 是否增量同步
+    pub sync: Option<bool>,
+}
+
+impl JobFile {
+    /// 读取并解析任务文件
+    pub fn load(path: &Path) -> Result<Self> {
+        let body = std::fs::read_to_string(path)?;
+        let file: JobFile = toml::from_str(&body).map_err(|e| {
+            FerrumenaError::Config(format!("任务文件 {} 解析失败: {}", path.display(), e))
+        })?;
+        if file.jobs.is_empty() {
+            return Err(FerrumenaError::Config(format!(
+                "任务文件 {} 中没有任何 [[job]]",
+                path.display()
+            )));
+        }
+        if file.parallel == 0 {
+            return Err(FerrumenaError::Config(
+                "任务文件中的 parallel 必须大于 0".to_string(),
+            ));
+        }
+        Ok(file)
+    }
+}
+
+impl Job {
+    /// 日志中显示的名称
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.query)
+    }
+
+    /// 以 `defaults` 为基础生成该任务的搜索请求
+    pub fn search(&self, defaults: &SearchRequest) -> SearchRequest {
+        let mut search = SearchRequest {
+            query: self.query.clone(),
+            sort_field: self
+                .sort_field
+                .clone()
+                .unwrap_or_else(|| defaults.sort_field.clone()),
+            sort_direction: self
+                .sort_direction
+                .clone()
+                .unwrap_or_else(|| defaults.sort_direction.clone()),
+            filter_id: self.filter_id.or(defaults.filter_id),
+            limit: self.limit.or(defaults.limit),
+            ..defaults.clone()
+        };
+        search.ensure_seed();
+        search
+    }
+
+    /// 以 `defaults` 为基础生成该任务的下载选项，`base_dir` 为任务文件所在目录
+    pub fn options(&self, defaults: &DownloadOptions, base_dir: &Path) -> DownloadOptions {
+        let mut options = defaults.clone();
+        if let Some(output) = &self.output {
+            options.save_path = base_dir.join(output);
+        }
+        if let Some(template) = &self.filename_template {
+            options.filename_template = template.clone();
+        }
+        if let Some(representation) = &self.representation {
+            options.representation = representation.clone();
+        }
+        if let Some(sync) = self.sync {
+            options.sync = sync;
+        }
+        options
+    }
+}
+
+/// 一个任务的运行结果
+#[derive(Debug)]
+pub struct JobOutcome {
+    pub name: String,
+    pub result: Result<RunSummary>,
+}
+
+/// 执行任务文件中的全部任务，按任务顺序返回各自的结果
+///
+/// 最多同时运行 `parallel` 个任务；同一站点的任务共用一个客户端（及其限速器），
+/// 不同站点各自限速。`config` 的 Cookie 只发送给与其 `base_url` 相同的站点。
+/// 单个任务失败不影响其它任务；`shutdown` 取消后不再开始新的任务。
+/// 不同站点的任务使用同一个保存目录时，不运行任何任务，直接返回配置错误
+pub async fn run<O>(
+    config: &FerrumenaConfig,
+    defaults: &SearchRequest,
+    file: &JobFile,
+    base_dir: &Path,
+    parallel: u32,
+    observer: O,
+    shutdown: CancellationToken,
+) -> Result<Vec<JobOutcome>>
+where
+    O: DownloadObserver + Clone + 'static,
+{
+    // 一个保存目录只属于一个站点，运行前先检查，避免跑到一半才失败
+    let base_options = DownloadOptions::from_config(config);
+    let mut owners: HashMap<PathBuf, String> = HashMap::new();
+    for job in &file.jobs {
+        let site = site_url(job.site.as_deref().unwrap_or(&config.base_url));
+        let save_path = job.options(&base_options, base_dir).save_path;
+        match owners.get(&save_path) {
+            Some(owner) if *owner != site => {
+                return Err(FerrumenaError::Config(format!(
+                    "任务 {} 的保存目录 {} 已被站点 {} 的任务使用，请为不同站点的任务设置不同的 output",
+                    job.name(),
+                    save_path.display(),
+                    owner
+                )));
+            }
+            _ => {
+                owners.insert(save_path, site);
+            }
+        }
+    }
+
+    // 每个站点一个客户端
+    let mut clients: HashMap<String, Arc<PhilomenaClient>> = HashMap::new();
+    for job in &file.jobs {
+        let site = site_url(job.site.as_deref().unwrap_or(&config.base_url));
+        if clients.contains_key(&site) {
+            continue;
+        }
+        let mut site_config = config.clone();
+        if site != site_url(&config.base_url) {
+            site_config.cookie = String::new();
+        }
+        site_config.base_url = site.clone();
+        let client = PhilomenaClient::new(site_config)?.observer(observer.clone());
+        clients.insert(site, Arc::new(client));
+    }
+
+    let count = file.jobs.len() as u32;
+    let outcomes = stream::iter(file.jobs.iter().enumerate())
+        .map(|(index, job)| {
+            let site = site_url(job.site.as_deref().unwrap_or(&config.base_url));
+            let client = Arc::clone(&clients[&site]);
+            let observer = observer.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                let name = job.name().to_string();
+                if shutdown.is_cancelled() {
+                    return None;
+                }
+                observer.on_event(&DownloadEvent::JobStarted {
+                    index: index as u32 + 1,
+                    count,
+                    name: &name,
+                });
+                let options = job.options(&DownloadOptions::from_config(&client.config), base_dir);
+                let result = async {
+                    Downloader::builder(client, job.search(defaults))
+                        .options(options)
+                        .shutdown(shutdown)
+                        .observer(observer.clone())
+                        .build()
+                        .await?
+                        .run()
+                        .await
+                }
+                .await;
+                if let Err(error) = &result {
+                    observer.on_event(&DownloadEvent::JobFailed { name: &name, error });
+                }
+                Some(JobOutcome { name, result })
+            }
+        })
+        .buffered(parallel.max(1) as usize)
+        .filter_map(|outcome| async move { outcome })
+        .collect()
+        .await;
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: &str) -> JobFile {
+        toml::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn job_search_falls_back_to_defaults() {
+        let file = parse(
+            r#"
+            [[job]]
+            query = "artist:a"
+
+            [[job]]
+            name = "b"
+            query = "artist:b"
+            sort_field = "score"
+            sort_direction = "asc"
+            filter_id = 2
+            limit = 10
+            "#,
+        );
+        assert_eq!(file.parallel, 1);
+        let defaults = SearchRequest::builder("ignored")
+            .sort_field(SortField::CreatedAt)
+            .filter_id(100)
+            .limit(500)
+            .build()
+            .unwrap();
+
+        let a = file.jobs[0].search(&defaults);
+        assert_eq!(file.jobs[0].name(), "artist:a");
+        assert_eq!(a.query, "artist:a");
+        assert_eq!(a.sort_field, SortField::CreatedAt);
+        assert_eq!(a.filter_id, Some(100));
+        assert_eq!(a.limit, Some(500));
+
+        let b = file.jobs[1].search(&defaults);
+        assert_eq!(file.jobs[1].name(), "b");
+        assert_eq!(b.sort_field, SortField::Score);
+        assert!(matches!(b.sort_direction, SortOrder::Asc));
+        assert_eq!(b.filter_id, Some(2));
+        assert_eq!(b.limit, Some(10));
+    }
+
+    #[test]
+    fn job_options_resolve_output_against_job_file() {
+        let file = parse(
+            r#"
+            [[job]]
+            query = "a"
+            output = "artists/a"
+            representation = "large"
+            sync = true
+
+            [[job]]
+            query = "b"
+            "#,
+        );
+        let defaults = DownloadOptions::from_config(&FerrumenaConfig::default());
+        let base_dir = Path::new("jobs");
+
+        let a = file.jobs[0].options(&defaults, base_dir);
+        assert_eq!(a.save_path, Path::new("jobs/artists/a"));
+        assert_eq!(a.representation, "large");
+        assert!(a.sync);
+        assert_eq!(a.filename_template, defaults.filename_template);
+
+        let b = file.jobs[1].options(&defaults, base_dir);
+        assert_eq!(b.save_path, defaults.save_path);
+        assert_eq!(b.representation, defaults.representation);
+        assert_eq!(b.sync, defaults.sync);
+    }
+
+    #[tokio::test]
+    async fn sites_cannot_share_output() {
+        let file = parse(
+            r#"
+            [[job]]
+            query = "a"
+
+            [[job]]
+            query = "b"
+            site = "https://derpibooru.org"
+            "#,
+        );
+        let config = FerrumenaConfig::default();
+        let defaults = SearchRequest::builder("x").build().unwrap();
+        let result = run(
+            &config,
+            &defaults,
+            &file,
+            Path::new("."),
+            1,
+            |_: &DownloadEvent<'_>| {},
+            CancellationToken::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(FerrumenaError::Config(_))));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<JobFile>("[[job]]\nquery = \"a\"\nouput = \"x\"\n").is_err());
+        assert!(toml::from_str::<JobFile>("paralel = 2\n").is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! # Ferrumena
+//!
+//! 异步 Philomena 图片下载器的核心库，命令行程序只是它的一个使用者。
+//!
+//! - [`PhilomenaClient`]：带限速、重试和错误识别的站点客户端，负责搜索结果的获取
+//! - [`Downloader`]：搜索并下载图片的引擎，通过 [`Downloader::builder`] 配置
+//! - [`DownloadObserver`]：订阅下载过程中的 [`DownloadEvent`]，命令行输出也是通过它实现的
+//! - [`FerrumenaConfig`]：站点、网络和存储相关的配置，可从环境变量加载
+//! - [`SearchRequest`]：一次搜索的参数（搜索句、排序、每页数量、过滤器、数量上限）
+//! - [`ImageItem`]：统一的图片元数据，JSON API 与 HTML 两种后端共用
+//! - [`watch::watch`]：常驻运行，按间隔轮询一组搜索并增量下载
+//! - [`jobs::run`]：执行 TOML 任务文件中描述的多个下载任务
+//!
+//! ## 示例
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use ferrumena::{Downloader, FerrumenaConfig, PhilomenaClient, SearchRequest, SortField};
+//!
+//! # async fn example() -> ferrumena::Result<()> {
+//! let config = FerrumenaConfig {
+//!     base_url: "https://trixiebooru.org/".to_string(),
+//!     ..FerrumenaConfig::default()
+//! };
+//! // 客户端可以在多个下载器之间共用，限速状态也随之共享
+//! let client = Arc::new(PhilomenaClient::new(config)?);
+//!
+//! let search = SearchRequest::builder("safe, pony")
+//!     .sort_field(SortField::Score)
+//!     .limit(200)
+//!     .build()?;
+//! let downloader = Downloader::builder(Arc::clone(&client), search)
+//!     .save_path("./downloads")
+//!     .filename_template("{artist}/{id}.{ext}")
+//!     .concurrency(8)
+//!     .build()
+//!     .await?;
+//! downloader.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+// 公开模块
+pub mod api;
+pub mod config;
+pub mod downloader;
+pub mod error;
+pub mod events;
+pub mod jobs;
+pub mod naming;
+pub mod partition;
+pub mod retry;
+pub mod search;
+pub mod sidecar;
+pub mod state;
+pub mod verify;
+pub mod watch;
+
+// 内部模块
+mod resume;
+mod utils;
+
+// 重定向导出，常用类型可直接从 crate 根引用
+pub use api::PhilomenaClient;
+pub use api::models::{DownloadTask, ImageItem, PageResponse};
+pub use config::{FerrumenaConfig, SearchBackend};
+pub use downloader::{DownloadOptions, Downloader, DownloaderBuilder, LimitMode};
+pub use error::{FerrumenaError, Result};
+pub use events::{DownloadEvent, DownloadObserver, RunSummary};
+pub use search::{SearchRequest, SortField, SortOrder};
@@ -1,18 +1,33 @@
-mod api;
 mod cli;
-mod config;
-mod downloader;
-mod error;
-mod utils;
+
 use clap::Parser;
-use downloader::Downloader;
+use ferrumena::events::ConsoleObserver;
+use ferrumena::jobs::{self, JobFile};
+use ferrumena::watch::{self, WatchJob};
+use ferrumena::{Downloader, LimitMode, PhilomenaClient, SearchRequest, config, error};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 
 #[tokio::main]
 async fn main() -> error::Result<()> {
     // 加载配置
-    let cfg = config::FerrumenaConfig::load();
+    let cfg = config::FerrumenaConfig::try_load().unwrap_or_else(|e| {
+        println!("⚠️  {}，将使用默认配置。", e);
+        config::FerrumenaConfig::default()
+    });
     let args = cli::Args::parse();
-    let cfg = cfg.merge_with_cli(&args);
+    let cfg = args.apply(cfg);
+    let search = SearchRequest::from(&args);
+    let job_file = match &args.command {
+        Some(cli::Command::Run(r)) => match JobFile::load(&r.file) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                std::process::exit(e.exit_code());
+            }
+        },
+        _ => None,
+    };
 
     // 打印配置信息
     println!("================================================================");
@@ -22,41 +37,162 @@ async fn main() -> error::Result<()> {
 
     println!("🔧 运行配置");
     println!("  ├─ 🌐 目标站点: {}", cfg.base_url);
-    println!("  ├─ 🔍 搜索句: {}", args.query);
+    match &args.command {
+        Some(cli::Command::Watch(w)) => {
+            for q in &w.queries {
+                println!(
+                    "  ├─ 👀 轮询搜索: {} (每 {} 秒)",
+                    q.query,
+                    q.interval.unwrap_or(w.interval).as_secs()
+                );
+            }
+        }
+        Some(cli::Command::Run(r)) => {
+            let file = job_file.as_ref().expect("任务文件已加载");
+            println!(
+                "  ├─ 📦 任务文件: {} ({} 个任务，同时运行 {} 个)",
+                r.file.display(),
+                file.jobs.len(),
+                r.parallel.unwrap_or(file.parallel)
+            );
+        }
+        None => println!("  ├─ 🔍 搜索句: {}", search.query),
+    }
     println!(
         "  ├─ 📊 排序: {} ({})",
-        args.sort_field.to_api_string(),
-        args.sort_direction
+        search.sort_param(),
+        search.sort_direction
     );
-    println!("  └─ 🎫 Filter ID: {}", cfg.filter_id);
+    if let Some(seed) = search.random_seed() {
+        println!("  ├─ 🎲 随机种子: {}", seed);
+    }
+    println!("  ├─ 🎫 Filter ID: {}", cfg.filter_id);
+    if cfg.sync || matches!(args.command, Some(cli::Command::Watch(_))) {
+        println!("  ├─ 🔁 增量同步: 开启");
+    }
+    println!("  └─ 🛰️ 搜索后端: {}", cfg.backend);
 
     println!("\n⚙️  性能参数");
-    println!("  ├─ 📄 每页图片: {} 张", args.per_page);
+    println!("  ├─ 📄 每页图片: {} 张", search.per_page);
+    println!("  ├─ 🧭 翻页方式: {}", search.pagination);
     println!("  ├─ 🚀 下载并发: {} Workers", cfg.concurrency);
+    println!("  ├─ 📑 翻页并发: {} Workers", cfg.page_workers);
+    if cfg.partitions > 1 {
+        println!(
+            "  ├─ 🧩 分区抓取: {} 个分区 (按 {})",
+            cfg.partitions, cfg.partition_by
+        );
+    }
     println!("  ├─ ⚡ 爬页限速: {} 请求/秒", cfg.rps);
+    let limit_or_unlimited = |v: u64, unit: &str| {
+        if v == 0 {
+            "不限".to_string()
+        } else {
+            format!("{} {}", v, unit)
+        }
+    };
+    println!(
+        "  ├─ 🖼️ 图片限速: {}, {} (每主机)",
+        limit_or_unlimited(cfg.image_rps as u64, "请求/秒"),
+        limit_or_unlimited(cfg.image_bandwidth, "字节/秒")
+    );
     println!("  ├─ 🧱 连续失败上限: {} 次", cfg.max_failures);
+    println!(
+        "  ├─ 🔁 重试策略: 最多 {} 次, 退避 {}ms ~ {}ms, 抖动 ±{:.0}%",
+        cfg.retry_max_attempts,
+        cfg.retry_base_delay_ms,
+        cfg.retry_max_delay_ms,
+        cfg.retry_jitter * 100.0
+    );
     println!("  └─ 🖼️ 图片质量级别: {}", cfg.representation);
 
     println!("\n💾 存储设置");
-    let limit_display = args
+    let limit_display = search
         .limit
-        .map(|l| format!("{} 张", l))
+        .map(|l| match cfg.limit_mode {
+            LimitMode::Total => format!("{} 张", l),
+            LimitMode::New => format!("{} 张（只计新下载）", l),
+        })
         .unwrap_or_else(|| "全部".to_string());
     println!("  ├─ 📁 保存路径: {}", cfg.save_path.display());
+    println!("  ├─ 🏷️ 文件名模板: {}", cfg.filename_template);
+    println!(
+        "  ├─ 🗂️ 元数据 Sidecar: {}",
+        if cfg.sidecar { "开启" } else { "关闭" }
+    );
+    println!(
+        "  ├─ 🔐 SHA-512 校验: {}",
+        if cfg.verify { "开启" } else { "关闭" }
+    );
     println!("  └─ 🎯 目标数量: {}", limit_display);
     println!();
 
+    // Ctrl+C / SIGTERM 时等进行中的下载完成后退出
+    let shutdown = CancellationToken::new();
+    tokio::spawn(handle_signals(shutdown.clone()));
+
+    // 任务文件中的任务可能分属不同站点，客户端按站点分别创建
+    if let (Some(cli::Command::Run(r)), Some(file)) = (&args.command, &job_file) {
+        println!("─────────────────────────────────────────────────────────────\n");
+        let base_dir = r.file.parent().unwrap_or(std::path::Path::new("."));
+        let parallel = r.parallel.unwrap_or(file.parallel);
+        let outcomes = match jobs::run(
+            &cfg,
+            &search,
+            file,
+            base_dir,
+            parallel,
+            ConsoleObserver,
+            shutdown.clone(),
+        )
+        .await
+        {
+            Ok(outcomes) => outcomes,
+            Err(e) => {
+                eprintln!("\n❌ 任务初始化失败: {:#?}", e);
+                std::process::exit(e.exit_code());
+            }
+        };
+
+        println!("\n📋 任务结果");
+        let mut exit_code = 0;
+        for outcome in &outcomes {
+            match &outcome.result {
+                Ok(s) => println!(
+                    "  ✅ {}: 下载 {} 张，跳过 {} 张，失败 {} 张",
+                    outcome.name, s.downloaded, s.skipped, s.failed
+                ),
+                Err(e) => {
+                    println!("  ❌ {}: {}", outcome.name, e);
+                    if exit_code == 0 {
+                        exit_code = e.exit_code();
+                    }
+                }
+            }
+        }
+        if outcomes.len() < file.jobs.len() {
+            println!(
+                "  ⏹️ 已中断，{} 个任务未运行",
+                file.jobs.len() - outcomes.len()
+            );
+        }
+        if exit_code != 0 {
+            std::process::exit(exit_code);
+        }
+        return Ok(());
+    }
+
     // 初始化客户端
     println!("─────────────────────────────────────────────────────────────");
     println!("🔌 正在初始化 API 客户端...");
-    let api_client = match api::PhilomenaClient::new(cfg) {
+    let api_client = match PhilomenaClient::new(cfg) {
         Ok(client) => {
             println!("   ✅ 客户端初始化成功");
-            client
+            client.observer(ConsoleObserver)
         }
         Err(e) => {
             eprintln!("   ❌ 初始化失败: {:#?}", e);
-            std::process::exit(1);
+            std::process::exit(e.exit_code());
         }
     };
 
@@ -64,35 +200,64 @@ async fn main() -> error::Result<()> {
     println!("\n🧪 正在测试网络连接...");
     match api_client.fetch_home().await {
         Ok(html) => {
-            let is_cf = html.contains("Just a moment...");
-            if is_cf {
-                println!("   ⚠️  检测到 Cloudflare 防护，可能需要配置 Cookie/UA 才能继续");
-            } else {
-                println!("   ✅ 网络连接正常，响应大小: {} bytes", html.len());
-            }
+            println!("   ✅ 网络连接正常，响应大小: {} bytes", html.len());
+        }
+        // 常驻模式会在每一轮重试，暂时的网络故障不必退出
+        Err(e) if args.command.is_some() && !e.is_fatal() => {
+            eprintln!("   ⚠️ 网络连接失败，将在轮询时重试: {}", e);
         }
         Err(e) => {
-            eprintln!("   ❌ 网络连接失败: {:#?}", e);
-            std::process::exit(1);
+            eprintln!("   ❌ 网络连接失败: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+
+    if let Some(cli::Command::Watch(w)) = &args.command {
+        let jobs = w
+            .queries
+            .iter()
+            .map(|q| WatchJob {
+                search: args.search_for(&q.query),
+                interval: q.interval.unwrap_or(w.interval),
+            })
+            .collect();
+        println!("\n👀 进入常驻模式，按 Ctrl+C 退出");
+        println!("─────────────────────────────────────────────────────────────\n");
+        if let Err(e) = watch::watch(Arc::new(api_client), jobs, ConsoleObserver, shutdown).await {
+            eprintln!("\n❌ 常驻模式出错: {:#?}", e);
+            std::process::exit(e.exit_code());
         }
+        println!("\n👋 常驻模式已退出");
+        return Ok(());
     }
 
     // 启动下载器
     println!("\n🚀 正在启动下载引擎...");
-    let downloader = match Downloader::new(api_client, args).await {
+    let downloader = match Downloader::builder(api_client, search)
+        .observer(ConsoleObserver)
+        .shutdown(shutdown.clone())
+        .build()
+        .await
+    {
         Ok(dl) => {
-            println!("   ✅ 下载器初始化完成");
+            println!(
+                "   ✅ 下载器初始化完成，本地已有 {} 张图片",
+                dl.existing_count()
+            );
             dl
         }
         Err(e) => {
             eprintln!("   ❌ 下载器初始化失败: {:#?}", e);
-            std::process::exit(1);
+            std::process::exit(e.exit_code());
         }
     };
 
     println!("─────────────────────────────────────────────────────────────\n");
 
     match downloader.run().await {
+        Ok(_) if shutdown.is_cancelled() => {
+            println!("\n⏹️  下载已中断，再次运行相同命令即可继续");
+        }
         Ok(_) => {
             println!("\n=====================================================");
             println!("              ✨ 下载任务已完成 ✨");
@@ -104,9 +269,39 @@ async fn main() -> error::Result<()> {
             eprintln!("                ❌ 下载过程中出错");
             eprintln!("=====================================================");
             eprintln!("错误详情: {:#?}\n", e);
-            std::process::exit(1);
+            std::process::exit(e.exit_code());
         }
     }
 
     Ok(())
 }
+
+/// 第一次收到退出信号时取消令牌，等待进行中的下载完成；再次收到时立即退出
+async fn handle_signals(shutdown: CancellationToken) {
+    wait_for_signal().await;
+    println!("\n🛑 收到退出信号，等待进行中的下载完成...（再次按 Ctrl+C 立即退出）");
+    shutdown.cancel();
+    wait_for_signal().await;
+    std::process::exit(130);
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+    match signal(SignalKind::terminate()) {
+        Ok(mut term) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,447 @@
+use crate::api::models::ImageItem;
+use crate::error::{FerrumenaError, Result};
+use chrono::format::{Item, StrftimeItems};
+use std::path::PathBuf;
+
+/// 默认文件名模板，与旧版本的 `{id}.{ext}` 命名一致
+pub const DEFAULT_TEMPLATE: &str = "{id}.{ext}";
+
+/// 单个占位符渲染后的最大字节数（超出部分截断）
+const FIELD_MAX_BYTES: usize = 100;
+
+/// 单级路径（文件名或目录名）的最大字节数
+const COMPONENT_MAX_BYTES: usize = 240;
+
+/// `{created_at}` 不带格式时的默认格式
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Windows 下的保留文件名
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone)]
+enum Field {
+    Id,
+    Ext,
+    /// `artist:` 开头的标签，去掉前缀后用 `+` 连接
+    Artist,
+    /// 全部标签或前 N 个标签，用 `,` 连接
+    Tags(Option<usize>),
+    /// strftime 格式，允许包含 `/` 以生成子目录
+    CreatedAt(String),
+    Score,
+    Faves,
+    Width,
+    Height,
+    Uploader,
+    Sha512,
+}
+
+impl Field {
+    /// 长度不定的自由文本字段，单级路径超长时优先截断它们
+    fn is_free_text(&self) -> bool {
+        matches!(self, Field::Artist | Field::Tags(_) | Field::Uploader)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// 文件名模板
+///
+/// 例如 `{id}__{artist}__{tags:5}.{ext}` 或 `{created_at:%Y}/{id}.{ext}`，
+/// `/` 用于分隔子目录。模板必须包含 `{id}` 和 `{ext}`，以便扫描时反推图片 ID
+#[derive(Debug, Clone)]
+pub struct FilenameTemplate {
+    segments: Vec<Segment>,
+}
+
+impl FilenameTemplate {
+    /// 解析模板字符串
+    pub fn parse(template: &str) -> Result<Self> {
+        let err =
+            |msg: String| FerrumenaError::Config(format!("文件名模板 '{}' {}", template, msg));
+
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| err("缺少与 '{' 对应的 '}'".to_string()))?;
+                    segments.push(Segment::Field(
+                        Self::parse_field(&rest[1..end]).map_err(err)?,
+                    ));
+                    rest = &rest[end + 1..];
+                }
+                Some(pos) => {
+                    segments.push(Segment::Literal(rest[..pos].to_string()));
+                    rest = &rest[pos..];
+                }
+                None => {
+                    segments.push(Segment::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+
+        // 字面量只允许合法字符，`/` 作为目录分隔符
+        for seg in &segments {
+            if let Segment::Literal(lit) = seg {
+                if lit.contains('}') {
+                    return Err(err("存在多余的 '}'".to_string()));
+                }
+                if lit.chars().any(|c| is_illegal_char(c) && c != '/') {
+                    return Err(err(format!("包含非法字符: '{}'", lit)));
+                }
+            }
+        }
+        if template.starts_with('/') || template.split('/').any(|c| c == "..") {
+            return Err(err("不能是绝对路径或包含 '..'".to_string()));
+        }
+
+        let has = |f: fn(&Field) -> bool| {
+            segments
+                .iter()
+                .any(|s| matches!(s, Segment::Field(field) if f(field)))
+        };
+        if !has(|f| matches!(f, Field::Id)) || !has(|f| matches!(f, Field::Ext)) {
+            return Err(err("必须同时包含 {id} 和 {ext}".to_string()));
+        }
+
+        Ok(Self { segments })
+    }
+
+    fn parse_field(spec: &str) -> std::result::Result<Field, String> {
+        let (name, arg) = match spec.split_once(':') {
+            Some((n, a)) => (n, Some(a)),
+            None => (spec, None),
+        };
+        let field = match (name, arg) {
+            ("id", None) => Field::Id,
+            ("ext", None) => Field::Ext,
+            ("artist", None) => Field::Artist,
+            ("tags", None) => Field::Tags(None),
+            ("tags", Some(n)) => Field::Tags(Some(
+                n.parse()
+                    .map_err(|_| format!("中 {{tags:{}}} 的数量无效", n))?,
+            )),
+            ("created_at", fmt) => {
+                let fmt = fmt.unwrap_or(DEFAULT_DATE_FORMAT);
+                if StrftimeItems::new(fmt).any(|item| matches!(item, Item::Error)) {
+                    return Err(format!("中的日期格式无效: '{}'", fmt));
+                }
+                Field::CreatedAt(fmt.to_string())
+            }
+            ("score", None) => Field::Score,
+            ("faves", None) => Field::Faves,
+            ("width", None) => Field::Width,
+            ("height", None) => Field::Height,
+            ("uploader", None) => Field::Uploader,
+            ("sha512", None) => Field::Sha512,
+            _ => return Err(format!("包含未知占位符: {{{}}}", spec)),
+        };
+        Ok(field)
+    }
+
+    /// 渲染出相对于保存目录的文件路径
+    pub fn render(&self, image: &ImageItem, ext: &str) -> PathBuf {
+        // 按 `/` 分级，每级由若干片段组成，并记下哪些片段可以截断
+        let mut components: Vec<Vec<(String, bool)>> = vec![Vec::new()];
+        for seg in &self.segments {
+            let (text, free) = match seg {
+                Segment::Literal(lit) => (lit.clone(), false),
+                Segment::Field(field) => (render_field(field, image, ext), field.is_free_text()),
+            };
+            for (i, part) in text.split('/').enumerate() {
+                if i > 0 {
+                    components.push(Vec::new());
+                }
+                if let Some(pieces) = components.last_mut() {
+                    pieces.push((part.to_string(), free));
+                }
+            }
+        }
+
+        // 逐级截断并清理，最后一级保留扩展名
+        let last = components.len() - 1;
+        components
+            .into_iter()
+            .enumerate()
+            .map(|(i, pieces)| finalize_component(&fit_component(pieces), i == last))
+            .collect()
+    }
+
+    /// 从相对路径（以 `/` 分隔）中反推图片 ID，不符合模板时返回 None
+    pub fn extract_id(&self, rel_path: &str) -> Option<u32> {
+        match_segments(&self.segments, rel_path, None)
+    }
+}
+
+fn render_field(field: &Field, image: &ImageItem, ext: &str) -> String {
+    let value = match field {
+        Field::Id => return image.id.to_string(),
+        Field::Ext => return sanitize(ext),
+        Field::Artist => {
+            let artists: Vec<&str> = image
+                .tags
+                .iter()
+                .filter_map(|t| t.strip_prefix("artist:"))
+                .collect();
+            if artists.is_empty() {
+                "unknown_artist".to_string()
+            } else {
+                artists.join("+")
+            }
+        }
+        Field::Tags(limit) => {
+            let n = limit.unwrap_or(image.tags.len());
+            image
+                .tags
+                .iter()
+                .take(n)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(",")
+        }
+        Field::CreatedAt(fmt) => {
+            // 日期格式允许用 `/` 生成子目录，逐级清理
+            return match image.created_at {
+                Some(dt) => dt
+                    .format(fmt)
+                    .to_string()
+                    .split('/')
+                    .map(|part| truncate_bytes(&sanitize(part), FIELD_MAX_BYTES).to_string())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                None => "unknown".to_string(),
+            };
+        }
+        Field::Score => opt_to_string(image.score),
+        Field::Faves => opt_to_string(image.faves),
+        Field::Width => opt_to_string(image.width),
+        Field::Height => opt_to_string(image.height),
+        Field::Uploader => image
+            .uploader
+            .clone()
+            .unwrap_or_else(|| "anonymous".to_string()),
+        Field::Sha512 => image
+            .sha512_hash
+            .clone()
+            .unwrap_or_else(|| "unknown".to_string()),
+    };
+    truncate_bytes(&sanitize(&value), FIELD_MAX_BYTES).to_string()
+}
+
+fn opt_to_string<T: ToString>(value: Option<T>) -> String {
+    value
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Windows 与 Linux 文件名中不允许（或不宜）出现的字符
+fn is_illegal_char(c: char) -> bool {
+    matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') || c.is_control()
+}
+
+/// 把非法字符替换为 `_`
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if is_illegal_char(c) { '_' } else { c })
+        .collect()
+}
+
+/// 按字节截断，保证落在字符边界上
+fn truncate_bytes(value: &str, max: usize) -> &str {
+    if value.len() <= max {
+        return value;
+    }
+    let mut end = max;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// 单级路径超长时只截断自由文本字段，从后往前截，
+/// 字面量、`{id}` 和 `{ext}` 保持完整，扫描时仍能反推出 ID
+fn fit_component(mut pieces: Vec<(String, bool)>) -> String {
+    let total: usize = pieces.iter().map(|(piece, _)| piece.len()).sum();
+    let mut excess = total.saturating_sub(COMPONENT_MAX_BYTES);
+    for (piece, free) in pieces.iter_mut().rev() {
+        if excess == 0 {
+            break;
+        }
+        if !*free {
+            continue;
+        }
+        let kept = truncate_bytes(piece, piece.len().saturating_sub(excess)).len();
+        excess = excess.saturating_sub(piece.len() - kept);
+        piece.truncate(kept);
+    }
+    pieces.into_iter().map(|(piece, _)| piece).collect()
+}
+
+/// 处理单级路径：截断长度、去掉末尾的点和空格、避开保留名
+///
+/// 长度通常已由 [`fit_component`] 控制，这里的截断只是兜底
+fn finalize_component(component: &str, keep_ext: bool) -> String {
+    let mut name = if component.len() > COMPONENT_MAX_BYTES {
+        match component.rsplit_once('.').filter(|_| keep_ext) {
+            Some((stem, ext)) => {
+                let budget = COMPONENT_MAX_BYTES.saturating_sub(ext.len() + 1);
+                format!("{}.{}", truncate_bytes(stem, budget), ext)
+            }
+            None => truncate_bytes(component, COMPONENT_MAX_BYTES).to_string(),
+        }
+    } else {
+        component.to_string()
+    };
+
+    let trimmed_len = name.trim_end_matches(['.', ' ']).len();
+    name.truncate(trimmed_len);
+
+    if name.is_empty() {
+        return "_".to_string();
+    }
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// 回溯匹配模板，`{id}` 匹配数字，其余占位符匹配任意非 `/` 字符（可为空）
+fn match_segments(segments: &[Segment], input: &str, id: Option<u32>) -> Option<u32> {
+    let Some((first, rest)) = segments.split_first() else {
+        return if input.is_empty() { id } else { None };
+    };
+
+    match first {
+        Segment::Literal(lit) => input
+            .strip_prefix(lit.as_str())
+            .and_then(|remain| match_segments(rest, remain, id)),
+        Segment::Field(Field::Id) => {
+            let digits = input.bytes().take_while(u8::is_ascii_digit).count();
+            (1..=digits).rev().find_map(|len| {
+                let parsed = input[..len].parse().ok()?;
+                match_segments(rest, &input[len..], Some(parsed))
+            })
+        }
+        Segment::Field(field) => {
+            let allow_slash = matches!(field, Field::CreatedAt(_));
+            let max = input
+                .char_indices()
+                .find(|&(_, c)| c == '/' && !allow_slash)
+                .map(|(i, _)| i)
+                .unwrap_or(input.len());
+            (0..=max)
+                .rev()
+                .filter(|&len| input.is_char_boundary(len))
+                .find_map(|len| match_segments(rest, &input[len..], id))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(id: u32, tags: &[&str]) -> ImageItem {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "representations": {},
+            "view_url": "",
+            "format": "png",
+            "tags": tags,
+            "tag_ids": [],
+            "source_urls": [],
+            "score": 42,
+            "created_at": "2024-03-05T12:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    fn rel_path(path: PathBuf) -> String {
+        path.to_string_lossy().replace('\\', "/")
+    }
+
+    #[test]
+    fn parse_rejects_invalid_templates() {
+        assert!(FilenameTemplate::parse("{artist}.{ext}").is_err());
+        assert!(FilenameTemplate::parse("{id}").is_err());
+        assert!(FilenameTemplate::parse("{id}.{ext").is_err());
+        assert!(FilenameTemplate::parse("{id}}.{ext}").is_err());
+        assert!(FilenameTemplate::parse("{id}.{ext}{unknown}").is_err());
+        assert!(FilenameTemplate::parse("{tags:x}/{id}.{ext}").is_err());
+        assert!(FilenameTemplate::parse("../{id}.{ext}").is_err());
+        assert!(FilenameTemplate::parse("/{id}.{ext}").is_err());
+        assert!(FilenameTemplate::parse("a?{id}.{ext}").is_err());
+        assert!(FilenameTemplate::parse(DEFAULT_TEMPLATE).is_ok());
+    }
+
+    #[test]
+    fn render_and_extract_round_trip() {
+        let img = image(1234, &["artist:foo", "safe", "pony"]);
+        let cases = [
+            (DEFAULT_TEMPLATE, "1234.png"),
+            (
+                "{id}__{artist}__{tags:2}.{ext}",
+                "1234__foo__artist_foo,safe.png",
+            ),
+            ("{score}-{id}.{ext}", "42-1234.png"),
+        ];
+        for (template, expected) in cases {
+            let template = FilenameTemplate::parse(template).unwrap();
+            let path = rel_path(template.render(&img, "png"));
+            assert_eq!(path, expected);
+            assert_eq!(template.extract_id(&path), Some(1234));
+        }
+    }
+
+    #[test]
+    fn render_subdirectories() {
+        let img = image(7, &["artist:a/b"]);
+        let template = FilenameTemplate::parse("{created_at:%Y/%m}/{artist}/{id}.{ext}").unwrap();
+        let path = rel_path(template.render(&img, "jpg"));
+        assert_eq!(path, "2024/03/a_b/7.jpg");
+        assert_eq!(template.extract_id(&path), Some(7));
+        assert_eq!(template.extract_id("2024/03/a_b/x7.jpg"), None);
+    }
+
+    #[test]
+    fn long_tags_keep_id_and_ext() {
+        let tags: Vec<String> = (0..200).map(|i| format!("tag number {}", i)).collect();
+        let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+        let img = image(987654, &tags);
+        for template in [
+            "{tags}_{tags:50}_{tags}_{id}.{ext}",
+            "{id}_{tags}_{tags}_{tags}.{ext}",
+            "{tags}/{tags}_{tags}_{tags}-{id}.{ext}",
+        ] {
+            let template = FilenameTemplate::parse(template).unwrap();
+            let path = rel_path(template.render(&img, "webm"));
+            assert!(
+                path.split('/').all(|c| c.len() <= COMPONENT_MAX_BYTES),
+                "{}",
+                path
+            );
+            assert!(path.ends_with(".webm"), "{}", path);
+            assert_eq!(template.extract_id(&path), Some(987654));
+        }
+    }
+
+    #[test]
+    fn reserved_names_are_escaped() {
+        let img = image(1, &[]);
+        let template = FilenameTemplate::parse("con/{id}.{ext}").unwrap();
+        assert_eq!(rel_path(template.render(&img, "png")), "_con/1.png");
+    }
+}
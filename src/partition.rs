@@ -0,0 +1,287 @@
+use crate::api::client::PhilomenaClient;
+use crate::api::models::ImageItem;
+use crate::error::{FerrumenaError, Result};
+use crate::search::{PaginationMode, SearchRequest, SortField, SortOrder};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use std::fmt;
+
+/// 每个分区先切成多少个等宽小段，再按图片数合并
+const SLICES_PER_PARTITION: u32 = 4;
+
+/// 分区依据的字段
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionKey {
+    /// 按图片 ID 范围分区
+    #[default]
+    Id,
+    /// 按上传时间范围分区
+    CreatedAt,
+}
+
+impl fmt::Display for PartitionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.field())
+    }
+}
+
+impl PartitionKey {
+    /// 搜索句中的字段名
+    fn field(&self) -> &'static str {
+        match self {
+            PartitionKey::Id => "id",
+            PartitionKey::CreatedAt => "created_at",
+        }
+    }
+
+    fn sort_field(&self) -> SortField {
+        match self {
+            PartitionKey::Id => SortField::Id,
+            PartitionKey::CreatedAt => SortField::CreatedAt,
+        }
+    }
+
+    /// 图片在该字段上的取值，时间取 Unix 秒
+    fn value(&self, img: &ImageItem) -> Option<i64> {
+        match self {
+            PartitionKey::Id => Some(img.id as i64),
+            PartitionKey::CreatedAt => img.created_at.map(|t| t.timestamp()),
+        }
+    }
+
+    /// 把取值格式化为搜索句可用的形式
+    fn format(&self, value: i64) -> String {
+        match self {
+            PartitionKey::Id => value.to_string(),
+            PartitionKey::CreatedAt => DateTime::<Utc>::from_timestamp(value, 0)
+                .unwrap_or_default()
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    /// 左闭右开的范围条件
+    fn range(&self, lo: i64, hi: i64) -> String {
+        format!(
+            "({f}.gte:{lo} && {f}.lt:{hi})",
+            f = self.field(),
+            lo = self.format(lo),
+            hi = self.format(hi)
+        )
+    }
+}
+
+/// 一个分区：原搜索句加上范围条件
+#[derive(Debug, Clone)]
+pub struct Partition {
+    /// 范围条件，如 `(id.gte:100 && id.lt:200)`
+    pub constraint: String,
+    /// 规划时分区内的图片数
+    pub total: u32,
+}
+
+impl Partition {
+    /// 该分区对应的搜索请求
+    pub fn apply(&self, search: &SearchRequest) -> SearchRequest {
+        SearchRequest {
+            partition: Some(self.constraint.clone()),
+            cursor: None,
+            ..search.clone()
+        }
+    }
+}
+
+/// 把搜索切分为最多 `count` 个互不重叠的范围，各分区图片数大致相等
+///
+/// 先用 `per_page=1` 的请求查出字段的最小值和最大值，把区间等宽切成
+/// `count * SLICES_PER_PARTITION` 段并逐段查询总数，再把相邻小段合并成每个约
+/// `总数 / count` 张的分区。没有图片的分区不会返回
+pub async fn plan(
+    client: &PhilomenaClient,
+    search: &SearchRequest,
+    key: PartitionKey,
+    count: u32,
+) -> Result<Vec<Partition>> {
+    let (Some(min), Some(max)) = (
+        probe_edge(client, search, key, SortOrder::Asc).await?,
+        probe_edge(client, search, key, SortOrder::Desc).await?,
+    ) else {
+        return Ok(Vec::new());
+    };
+
+    let mut counted = Vec::new();
+    for (lo, hi) in slice(min, max, count) {
+        let total = count_range(client, search, &key.range(lo, hi)).await?;
+        counted.push((lo, hi, total));
+    }
+
+    Ok(merge(&counted, count)
+        .into_iter()
+        .map(|(lo, hi, total)| Partition {
+            constraint: key.range(lo, hi),
+            total,
+        })
+        .collect())
+}
+
+/// 把 `[min, max]` 等宽切成最多 `count * SLICES_PER_PARTITION` 个左闭右开的小段，
+/// 右端点 +1 让最大值落在最后一段内
+fn slice(min: i64, max: i64, count: u32) -> Vec<(i64, i64)> {
+    let slices = (count.max(1) * SLICES_PER_PARTITION) as i64;
+    let span = max - min + 1;
+    let width = ((span + slices - 1) / slices).max(1);
+    let mut bounds = Vec::new();
+    let mut lo = min;
+    while lo <= max {
+        let hi = (lo + width).min(max + 1);
+        bounds.push((lo, hi));
+        lo = hi;
+    }
+    bounds
+}
+
+/// 把相邻的 `(lo, hi, 图片数)` 小段合并成约 `总数 / count` 张的范围，
+/// 跳过没有图片的范围
+fn merge(counted: &[(i64, i64, u32)], count: u32) -> Vec<(i64, i64, u32)> {
+    let sum: u64 = counted.iter().map(|&(_, _, t)| t as u64).sum();
+    let target = sum.div_ceil(count.max(1) as u64).max(1);
+    let mut merged = Vec::new();
+    let Some(&(mut start, _, _)) = counted.first() else {
+        return merged;
+    };
+    let mut acc: u64 = 0;
+    for (i, &(_, hi, total)) in counted.iter().enumerate() {
+        acc += total as u64;
+        let last = i + 1 == counted.len();
+        if acc >= target || last {
+            if acc > 0 {
+                merged.push((start, hi, acc as u32));
+            }
+            start = hi;
+            acc = 0;
+        }
+    }
+    merged
+}
+
+/// 按字段升序或降序取第一张图片的值，即最小值或最大值
+async fn probe_edge(
+    client: &PhilomenaClient,
+    search: &SearchRequest,
+    key: PartitionKey,
+    direction: SortOrder,
+) -> Result<Option<i64>> {
+    let req = SearchRequest {
+        sort_field: key.sort_field(),
+        sort_direction: direction,
+        ..probe_request(search, None)
+    };
+    let resp = client.fetch_page(1, &req).await?;
+    match resp.images.first() {
+        Some(img) => key.value(img).map(Some).ok_or_else(|| {
+            FerrumenaError::Logic(format!(
+                "图片 ID {} 缺少字段 {}，无法分区",
+                img.id,
+                key.field()
+            ))
+        }),
+        None => Ok(None),
+    }
+}
+
+/// 查询范围内的图片数
+async fn count_range(
+    client: &PhilomenaClient,
+    search: &SearchRequest,
+    constraint: &str,
+) -> Result<u32> {
+    let req = probe_request(search, Some(constraint.to_string()));
+    client
+        .fetch_page(1, &req)
+        .await?
+        .total
+        .ok_or_else(|| FerrumenaError::Logic("分区查询未获取到总数信息".to_string()))
+}
+
+/// 只取一张图片的探测请求，保留搜索句和过滤器
+fn probe_request(search: &SearchRequest, partition: Option<String>) -> SearchRequest {
+    SearchRequest {
+        per_page: 1,
+        limit: None,
+        pagination: PaginationMode::Page,
+        partition,
+        cursor: None,
+        ..search.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 各范围左闭右开、按顺序排列且互不重叠
+    fn assert_disjoint(ranges: &[(i64, i64)]) {
+        for &(lo, hi) in ranges {
+            assert!(lo < hi, "{:?}", ranges);
+        }
+        for pair in ranges.windows(2) {
+            assert!(pair[0].1 <= pair[1].0, "{:?}", ranges);
+        }
+    }
+
+    #[test]
+    fn slices_cover_whole_range() {
+        for (min, max, count) in [(1, 1, 4), (1, 10, 4), (100, 3_000_000, 8), (0, 7, 1)] {
+            let bounds = slice(min, max, count);
+            assert_disjoint(&bounds);
+            assert_eq!(bounds.first().unwrap().0, min);
+            assert_eq!(bounds.last().unwrap().1, max + 1);
+            assert!(bounds.windows(2).all(|p| p[0].1 == p[1].0));
+            assert!(bounds.len() as u32 <= count * SLICES_PER_PARTITION);
+        }
+    }
+
+    #[test]
+    fn merged_ranges_are_disjoint_and_keep_every_image() {
+        let totals: [&[u32]; 5] = [
+            &[10, 10, 10, 10, 10, 10, 10, 10],
+            &[0, 0, 50, 0, 0, 3, 0, 1],
+            &[100, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 0, 0, 0],
+            &[1],
+        ];
+        for totals in totals {
+            for count in 1..=4 {
+                let counted: Vec<_> = totals
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &t)| (i as i64 * 10, i as i64 * 10 + 10, t))
+                    .collect();
+                let merged = merge(&counted, count);
+                let ranges: Vec<_> = merged.iter().map(|&(lo, hi, _)| (lo, hi)).collect();
+                assert_disjoint(&ranges);
+                assert!(merged.iter().all(|&(_, _, t)| t > 0));
+
+                // 每个有图片的小段都完整落在某个合并后的范围内
+                for &(lo, hi, t) in &counted {
+                    if t > 0 {
+                        assert!(ranges.iter().any(|&(a, b)| a <= lo && hi <= b));
+                    }
+                }
+                let sum: u32 = totals.iter().sum();
+                assert_eq!(merged.iter().map(|&(_, _, t)| t).sum::<u32>(), sum);
+                assert!(merged.len() as u32 <= count);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_balances_uniform_slices() {
+        let counted: Vec<_> = (0..8).map(|i| (i * 10, i * 10 + 10, 25)).collect();
+        assert_eq!(
+            merge(&counted, 4),
+            vec![(0, 20, 50), (20, 40, 50), (40, 60, 50), (60, 80, 50)]
+        );
+    }
+}
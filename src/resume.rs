@@ -0,0 +1,90 @@
+use crate::error::Result;
+use reqwest::{Response, header};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// 下载中的临时文件扩展名，扫描时跳过，避免半截文件被当成已完成
+pub const PART_EXT: &str = "part";
+
+/// 临时文件对应的续传信息扩展名
+pub const PART_META_EXT: &str = "meta";
+
+/// `12345.png` -> `12345.png.part`
+pub fn part_path_for(file_path: &Path) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(".");
+    name.push(PART_EXT);
+    PathBuf::from(name)
+}
+
+/// `12345.png.part` -> `12345.png.part.meta`
+fn meta_path_for(part_path: &Path) -> PathBuf {
+    let mut name = part_path.as_os_str().to_owned();
+    name.push(".");
+    name.push(PART_META_EXT);
+    PathBuf::from(name)
+}
+
+/// 与 `.part` 文件一起保存的续传信息
+///
+/// 进程重启后用它判断服务器上的文件是否仍是同一个，
+/// 只有 URL 相同且有可用的校验器（强 ETag 或 Last-Modified）时才续传
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartMeta {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// 完整文件长度
+    pub total_len: Option<u64>,
+}
+
+impl PartMeta {
+    /// 从完整响应（200）中提取续传信息
+    pub fn from_response(url: &str, resp: &Response) -> Self {
+        let header_str = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            url: url.to_string(),
+            // 弱 ETag 不能用于 If-Range
+            etag: header_str(header::ETAG).filter(|e| !e.starts_with("W/")),
+            last_modified: header_str(header::LAST_MODIFIED),
+            total_len: resp.content_length(),
+        }
+    }
+
+    /// `If-Range` 请求头的值，优先使用 ETag
+    pub fn validator(&self) -> Option<&str> {
+        self.etag.as_deref().or(self.last_modified.as_deref())
+    }
+
+    /// 读取临时文件的续传信息，不存在或损坏时返回 None
+    pub async fn load(part_path: &Path) -> Option<Self> {
+        let body = tokio::fs::read(meta_path_for(part_path)).await.ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+
+    pub async fn save(&self, part_path: &Path) -> Result<()> {
+        let body = serde_json::to_vec(self)?;
+        tokio::fs::write(meta_path_for(part_path), body).await?;
+        Ok(())
+    }
+
+    /// 删除临时文件及其续传信息
+    pub async fn discard(part_path: &Path) {
+        let _ = tokio::fs::remove_file(part_path).await;
+        let _ = tokio::fs::remove_file(meta_path_for(part_path)).await;
+    }
+}
+
+/// 解析 `Content-Range: bytes <start>-<end>/<total>`，返回 (start, total)
+pub fn parse_content_range(resp: &Response) -> Option<(u64, Option<u64>)> {
+    let value = resp.headers().get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.strip_prefix("bytes ")?;
+    let (span, total) = range.split_once('/')?;
+    let (start, _) = span.split_once('-')?;
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()))
+}
@@ -0,0 +1,124 @@
+use crate::config::FerrumenaConfig;
+use crate::error::Result;
+use crate::events::DownloadEvent;
+use rand::RngExt;
+use std::future::Future;
+use std::time::Duration;
+
+/// 重试策略：指数退避 + 随机抖动
+///
+/// 页面抓取、首页测试和图片下载共用同一套策略，
+/// 只有 [`FerrumenaError::is_retryable`](crate::error::FerrumenaError::is_retryable)
+/// 判定为暂时性的错误才会重试
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 最多尝试次数（含第一次），1 表示不重试
+    pub max_attempts: u32,
+    /// 第一次重试前的等待时间
+    pub base_delay: Duration,
+    /// 单次等待的上限
+    pub max_delay: Duration,
+    /// 抖动比例（0.0 ~ 1.0），实际等待时间在 ±jitter 范围内随机浮动
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &FerrumenaConfig) -> Self {
+        Self {
+            max_attempts: config.retry_max_attempts.max(1),
+            base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            max_delay: Duration::from_millis(config.retry_max_delay_ms),
+            jitter: normalize_jitter(config.retry_jitter),
+        }
+    }
+
+    /// 第 attempt 次失败后的等待时间（attempt 从 1 开始）
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(16);
+        let delay = self.base_delay.saturating_mul(1 << exp).min(self.max_delay);
+
+        // 字段可以直接赋值，这里再规范一次，NaN 等异常值会让 random_range 恐慌
+        let jitter = normalize_jitter(self.jitter);
+        if jitter <= 0.0 {
+            return delay;
+        }
+        let factor = rand::rng().random_range(1.0 - jitter..=1.0 + jitter);
+        delay.mul_f64(factor).min(self.max_delay)
+    }
+
+    /// 按策略执行 op，暂时性错误时等待后重试，永久性错误立即返回
+    ///
+    /// 每次重试前以 [`DownloadEvent::Retrying`] 调用 `notify`
+    pub async fn run<T, F, Fut, N>(&self, what: &str, notify: N, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+        N: Fn(DownloadEvent<'_>),
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Ok(v) => return Ok(v),
+                Err(e) if e.is_retryable() && attempt < self.max_attempts => {
+                    let delay = self.delay_for(attempt);
+                    notify(DownloadEvent::Retrying {
+                        what,
+                        attempt,
+                        max_attempts: self.max_attempts,
+                        delay,
+                        error: &e,
+                    });
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// 把抖动比例限制在 0.0 ~ 1.0，NaN 和无穷大视为不抖动
+fn normalize_jitter(jitter: f64) -> f64 {
+    if jitter.is_finite() {
+        jitter.clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn delay_grows_and_is_capped() {
+        let p = policy(0.0);
+        assert_eq!(p.delay_for(1), Duration::from_millis(100));
+        assert_eq!(p.delay_for(2), Duration::from_millis(200));
+        assert_eq!(p.delay_for(3), Duration::from_millis(400));
+        assert_eq!(p.delay_for(30), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn abnormal_jitter_does_not_panic() {
+        for jitter in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -1.0, 5.0] {
+            let delay = policy(jitter).delay_for(1);
+            assert!(
+                delay <= Duration::from_millis(200),
+                "{}: {:?}",
+                jitter,
+                delay
+            );
+        }
+        assert_eq!(policy(f64::NAN).delay_for(1), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,427 @@
+use crate::api::models::ImageItem;
+use crate::error::{FerrumenaError, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rand::RngExt;
+use serde::Deserialize;
+use std::fmt;
+
+/// 每页图片数量上限，Philomena 不接受更大的值
+pub const MAX_PER_PAGE: u32 = 50;
+
+/// 一次搜索的全部参数
+///
+/// 与命令行无关，既可以由 [`cli::Args`](crate::cli::Args) 转换得到，
+/// 也可以通过 [`SearchRequest::builder`] 直接构造
+#[derive(Debug, Clone)]
+pub struct SearchRequest {
+    /// 搜索句，语法见 <https://trixiebooru.org/pages/search_syntax>
+    pub query: String,
+    /// 排序字段
+    pub sort_field: SortField,
+    /// 排序方向
+    pub sort_direction: SortOrder,
+    /// 每页图片数量 (1-50)
+    pub per_page: u32,
+    /// 过滤器 ID，None 时使用客户端配置中的值
+    pub filter_id: Option<u32>,
+    /// 最多抓取的图片数量，None 表示全部结果
+    pub limit: Option<u32>,
+    /// 随机排序的种子，仅在 `sort_field` 为 [`SortField::Random`] 时使用
+    pub seed: Option<u32>,
+    /// 翻页方式
+    pub pagination: PaginationMode,
+    /// 分区抓取时追加到搜索句的范围条件，由 [`partition::plan`](crate::partition::plan) 生成
+    pub partition: Option<String>,
+    /// 游标翻页时追加到搜索句的条件，由 [`SearchRequest::after`] 生成
+    pub cursor: Option<String>,
+    /// 增量同步时只抓取 ID 大于该值的图片（追加 `id.gt:<ID>`）
+    pub since_id: Option<u32>,
+}
+
+/// 翻页方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PaginationMode {
+    /// 使用 `page=N` 翻页，可以多页并发，但抓取期间有新图片上传时结果会错位
+    #[default]
+    Page,
+    /// 在搜索句中追加 `id.lt:<上一页最后的 ID>` 之类的条件逐页推进，
+    /// 结果稳定且没有深度限制，但只能按顺序抓取
+    Cursor,
+}
+
+impl fmt::Display for PaginationMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaginationMode::Page => write!(f, "page"),
+            PaginationMode::Cursor => write!(f, "cursor"),
+        }
+    }
+}
+
+impl SearchRequest {
+    /// 创建构建器，其余参数默认与命令行一致：按 ID 降序、每页 50 张、不限数量
+    pub fn builder(query: impl Into<String>) -> SearchRequestBuilder {
+        SearchRequestBuilder {
+            request: SearchRequest {
+                query: query.into(),
+                sort_field: SortField::Id,
+                sort_direction: SortOrder::Desc,
+                per_page: MAX_PER_PAGE,
+                filter_id: None,
+                limit: None,
+                seed: None,
+                pagination: PaginationMode::Page,
+                partition: None,
+                cursor: None,
+                since_id: None,
+            },
+        }
+    }
+
+    /// 实际发送的搜索句：追加同步、分区和游标条件
+    pub fn effective_query(&self) -> String {
+        let since = self.since_id.map(|id| format!("id.gt:{}", id));
+        let extra: Vec<&str> = [&since, &self.partition, &self.cursor]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if extra.is_empty() {
+            return self.query.clone();
+        }
+        format!("({}) && {}", self.query, extra.join(" && "))
+    }
+
+    /// 检查参数组合是否可用
+    pub fn validate(&self) -> Result<()> {
+        if self.query.trim().is_empty() {
+            return Err(FerrumenaError::Config("搜索句不能为空".to_string()));
+        }
+        if !(1..=MAX_PER_PAGE).contains(&self.per_page) {
+            return Err(FerrumenaError::Config(format!(
+                "每页图片数量必须在 1-{} 之间: {}",
+                MAX_PER_PAGE, self.per_page
+            )));
+        }
+        if self.limit == Some(0) {
+            return Err(FerrumenaError::Config("数量上限必须大于 0".to_string()));
+        }
+        if self.pagination == PaginationMode::Cursor && self.sort_field.cursor_field().is_none() {
+            return Err(FerrumenaError::Config(format!(
+                "排序字段 {} 不支持游标翻页",
+                self.sort_field.to_api_string()
+            )));
+        }
+        Ok(())
+    }
+
+    /// 游标翻页的下一页：只保留排在 `last` 之后的图片
+    ///
+    /// 按 ID 排序时条件为 `id.lt:<id>`；按其它字段排序时以 ID 作为次级排序，
+    /// 条件为 `(score.lt:<v> || (score:<v> && id.lt:<id>))`，升序时 `lt` 换成 `gt`
+    pub fn after(&self, last: &ImageItem) -> Result<Self> {
+        let field = self.sort_field.cursor_field().ok_or_else(|| {
+            FerrumenaError::Config(format!(
+                "排序字段 {} 不支持游标翻页",
+                self.sort_field.to_api_string()
+            ))
+        })?;
+        let op = match self.sort_direction {
+            SortOrder::Desc => "lt",
+            SortOrder::Asc => "gt",
+        };
+
+        let cursor = if field == "id" {
+            format!("id.{}:{}", op, last.id)
+        } else {
+            let value = self.sort_field.cursor_value(last).ok_or_else(|| {
+                FerrumenaError::Logic(format!(
+                    "图片 ID {} 缺少排序字段 {} 的值，无法继续游标翻页",
+                    last.id, field
+                ))
+            })?;
+            format!(
+                "({f}.{op}:{v} || ({f}:{v} && id.{op}:{id}))",
+                f = field,
+                op = op,
+                v = value,
+                id = last.id
+            )
+        };
+
+        Ok(Self {
+            cursor: Some(cursor),
+            ..self.clone()
+        })
+    }
+
+    /// 请求参数中的排序字段 `sf`
+    ///
+    /// 随机排序时为 `random:<seed>`；没有种子时只发送 `random`，
+    /// 站点会为每个请求各自生成种子，翻页结果将互相重叠，因此请先调用 [`ensure_seed`](Self::ensure_seed)
+    pub fn sort_param(&self) -> String {
+        match (&self.sort_field, self.seed) {
+            (SortField::Random, Some(seed)) => format!("random:{}", seed),
+            (field, _) => field.to_api_string(),
+        }
+    }
+
+    /// 随机排序且未指定种子时生成一个，之后每一页都使用同一个种子
+    pub fn ensure_seed(&mut self) {
+        if self.sort_field == SortField::Random && self.seed.is_none() {
+            self.seed = Some(rand::rng().random());
+        }
+    }
+
+    /// 随机排序实际使用的种子，其它排序为 None
+    pub fn random_seed(&self) -> Option<u32> {
+        match self.sort_field {
+            SortField::Random => self.seed,
+            _ => None,
+        }
+    }
+}
+
+/// [`SearchRequest`] 的构建器
+pub struct SearchRequestBuilder {
+    request: SearchRequest,
+}
+
+impl SearchRequestBuilder {
+    /// 排序字段
+    pub fn sort_field(mut self, field: SortField) -> Self {
+        self.request.sort_field = field;
+        self
+    }
+
+    /// 排序方向
+    pub fn sort_direction(mut self, direction: SortOrder) -> Self {
+        self.request.sort_direction = direction;
+        self
+    }
+
+    /// 每页图片数量 (1-50)
+    pub fn per_page(mut self, per_page: u32) -> Self {
+        self.request.per_page = per_page;
+        self
+    }
+
+    /// 过滤器 ID，覆盖客户端配置
+    pub fn filter_id(mut self, filter_id: u32) -> Self {
+        self.request.filter_id = Some(filter_id);
+        self
+    }
+
+    /// 最多抓取的图片数量
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.request.limit = Some(limit);
+        self
+    }
+
+    /// 随机排序的种子，不指定时 [`build`](Self::build) 会随机生成一个
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.request.seed = Some(seed);
+        self
+    }
+
+    /// 翻页方式
+    pub fn pagination(mut self, pagination: PaginationMode) -> Self {
+        self.request.pagination = pagination;
+        self
+    }
+
+    /// 只抓取 ID 大于 `id` 的图片
+    pub fn since_id(mut self, id: u32) -> Self {
+        self.request.since_id = Some(id);
+        self
+    }
+
+    /// 检查参数并生成 [`SearchRequest`]
+    pub fn build(mut self) -> Result<SearchRequest> {
+        self.request.validate()?;
+        self.request.ensure_seed();
+        Ok(self.request)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum SortField {
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    AspectRatio,
+    Faves,
+    Upvotes,
+    Downvotes,
+    Score,
+    Relevance,
+    Random,
+    Width,
+    Height,
+    CommentCount,
+    TagCount,
+    Pixels,
+    Size,
+    Duration,
+}
+
+impl SortField {
+    pub fn to_api_string(&self) -> String {
+        match self {
+            // Relevance -> _score
+            Self::Relevance => "_score".to_string(),
+            // Random -> random，种子由 SearchRequest::sort_param 附加
+            Self::Random => "random".to_string(),
+            // 多词字段
+            Self::CreatedAt => "created_at".to_string(),
+            Self::UpdatedAt => "updated_at".to_string(),
+            Self::AspectRatio => "aspect_ratio".to_string(),
+            Self::CommentCount => "comment_count".to_string(),
+            Self::TagCount => "tag_count".to_string(),
+            // 单词字段
+            _ => format!("{:?}", self).to_lowercase(),
+        }
+    }
+
+    /// 游标翻页时在搜索句中使用的字段名，不支持游标翻页时为 None
+    ///
+    /// 只有能从 [`ImageItem`] 取到值的字段才能作为游标。时间在条件中只精确到秒，
+    /// `created_at` 同一秒内的先后与 ID 一致，可以用 ID 区分；`updated_at` 不是，
+    /// 同一秒内更新的图片会被跳过，因此不支持
+    pub fn cursor_field(&self) -> Option<&'static str> {
+        match self {
+            Self::Id => Some("id"),
+            Self::CreatedAt => Some("created_at"),
+            Self::Score => Some("score"),
+            Self::Faves => Some("faves"),
+            Self::Upvotes => Some("upvotes"),
+            Self::Downvotes => Some("downvotes"),
+            Self::Width => Some("width"),
+            Self::Height => Some("height"),
+            Self::Size => Some("size"),
+            _ => None,
+        }
+    }
+
+    /// 图片在该排序字段上的取值，格式化为搜索句可用的形式
+    fn cursor_value(&self, img: &ImageItem) -> Option<String> {
+        let date = |t: DateTime<Utc>| t.to_rfc3339_opts(SecondsFormat::Secs, true);
+        match self {
+            Self::Id => Some(img.id.to_string()),
+            Self::CreatedAt => img.created_at.map(date),
+            Self::Score => img.score.map(|v| v.to_string()),
+            Self::Faves => img.faves.map(|v| v.to_string()),
+            Self::Upvotes => img.upvotes.map(|v| v.to_string()),
+            Self::Downvotes => img.downvotes.map(|v| v.to_string()),
+            Self::Width => img.width.map(|v| v.to_string()),
+            Self::Height => img.height.map(|v| v.to_string()),
+            Self::Size => img.size.map(|v| v.to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortOrder::Asc => write!(f, "asc"),
+            SortOrder::Desc => write!(f, "desc"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(id: u32, score: Option<i32>) -> ImageItem {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "representations": {},
+            "view_url": "",
+            "format": "png",
+            "tags": [],
+            "tag_ids": [],
+            "source_urls": [],
+            "score": score,
+            "created_at": "2024-03-05T12:34:56.789Z",
+        }))
+        .unwrap()
+    }
+
+    fn request(field: SortField, direction: SortOrder) -> SearchRequest {
+        SearchRequest::builder("safe")
+            .sort_field(field)
+            .sort_direction(direction)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn cursor_by_id() {
+        let desc = request(SortField::Id, SortOrder::Desc).after(&image(100, None));
+        assert_eq!(desc.unwrap().cursor.as_deref(), Some("id.lt:100"));
+
+        let asc = request(SortField::Id, SortOrder::Asc).after(&image(100, None));
+        assert_eq!(asc.unwrap().cursor.as_deref(), Some("id.gt:100"));
+    }
+
+    #[test]
+    fn cursor_by_other_field_breaks_ties_by_id() {
+        let desc = request(SortField::Score, SortOrder::Desc).after(&image(100, Some(-3)));
+        assert_eq!(
+            desc.unwrap().cursor.as_deref(),
+            Some("(score.lt:-3 || (score:-3 && id.lt:100))")
+        );
+
+        let asc = request(SortField::CreatedAt, SortOrder::Asc).after(&image(100, None));
+        assert_eq!(
+            asc.unwrap().cursor.as_deref(),
+            Some(
+                "(created_at.gt:2024-03-05T12:34:56Z || \
+                 (created_at:2024-03-05T12:34:56Z && id.gt:100))"
+            )
+        );
+    }
+
+    #[test]
+    fn cursor_replaces_previous_and_joins_query() {
+        let req = request(SortField::Id, SortOrder::Desc);
+        let next = req.after(&image(100, None)).unwrap();
+        let next = next.after(&image(50, None)).unwrap();
+        assert_eq!(next.effective_query(), "(safe) && id.lt:50");
+    }
+
+    #[test]
+    fn cursor_errors() {
+        // 缺少排序字段的值
+        let req = request(SortField::Score, SortOrder::Desc);
+        assert!(matches!(
+            req.after(&image(1, None)),
+            Err(FerrumenaError::Logic(_))
+        ));
+        // 排序字段不支持游标
+        let req = request(SortField::Random, SortOrder::Desc);
+        assert!(matches!(
+            req.after(&image(1, Some(1))),
+            Err(FerrumenaError::Config(_))
+        ));
+        // updated_at 同一秒内的先后与 ID 无关，不能用作游标
+        let req = SearchRequest::builder("safe")
+            .sort_field(SortField::UpdatedAt)
+            .pagination(PaginationMode::Cursor)
+            .build();
+        assert!(matches!(req, Err(FerrumenaError::Config(_))));
+    }
+}
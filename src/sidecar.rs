@@ -0,0 +1,52 @@
+use crate::api::models::ImageItem;
+use crate::error::Result;
+use crate::verify::Verification;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Sidecar 文件扩展名，扫描已下载文件时需要排除
+pub const SIDECAR_EXT: &str = "json";
+
+/// Sidecar 格式版本号，字段发生不兼容变化时递增
+pub const SIDECAR_VERSION: u32 = 1;
+
+/// 与图片同名的 `{id}.json` 元数据文件
+///
+/// 格式见 README「元数据 Sidecar」一节，外部工具可直接读取
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sidecar {
+    pub schema_version: u32,
+    /// 来源站点，如 `https://trixiebooru.org/`
+    pub site: String,
+    /// 实际下载的 representation 键
+    pub representation: String,
+    /// 实际下载的 URL
+    pub download_url: String,
+    /// 图片文件名（相对于 sidecar 所在目录）
+    pub file_name: String,
+    pub downloaded_at: DateTime<Utc>,
+    /// 本地文件的 SHA-512
+    pub sha512: String,
+    /// 与站点哈希比对的结果
+    pub verification: Verification,
+    /// 随机排序时使用的种子，其它排序不写入
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub random_seed: Option<u32>,
+    /// 下载时刻的完整元数据
+    pub image: ImageItem,
+}
+
+impl Sidecar {
+    /// 图片文件对应的 sidecar 路径
+    pub fn path_for(image_path: &Path) -> PathBuf {
+        image_path.with_extension(SIDECAR_EXT)
+    }
+
+    /// 写入 sidecar（格式化 JSON）
+    pub async fn write(&self, image_path: &Path) -> Result<()> {
+        let body = serde_json::to_vec_pretty(self)?;
+        tokio::fs::write(Self::path_for(image_path), body).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use crate::error::{FerrumenaError, Result};
+use crate::utils::site_url;
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 状态数据库文件名，位于保存目录下
+pub const STATE_DB_NAME: &str = ".ferrumena.db";
+
+/// 一条下载记录
+#[derive(Debug, Clone)]
+pub struct DownloadRecord {
+    pub image_id: u32,
+    /// 相对于保存目录的路径，统一使用 `/` 分隔
+    pub path: String,
+    /// 本地文件的 SHA-512，从没有 sidecar 的旧文件重建时为空
+    pub sha512: Option<String>,
+    pub size: u64,
+    /// 实际下载的 representation 键，从没有 sidecar 的旧文件重建时为空
+    pub representation: Option<String>,
+    pub downloaded_at: DateTime<Utc>,
+}
+
+/// 保存目录下的下载状态数据库（SQLite）
+///
+/// 按站点记录每张已下载的图片，是判断“已下载”的依据：
+/// 文件移动、改名或整理到子目录后也不会重复下载
+pub struct StateDb {
+    // rusqlite 的连接不能跨线程共享，写入量很小，直接加锁
+    conn: Mutex<Connection>,
+    site: String,
+}
+
+impl StateDb {
+    /// 保存目录对应的数据库路径
+    pub fn path_for(save_path: &Path) -> PathBuf {
+        save_path.join(STATE_DB_NAME)
+    }
+
+    /// 打开（不存在时创建）保存目录下的数据库，记录归属于 `site`
+    ///
+    /// 站点地址规范为以 `/` 结尾，末尾斜杠不同的地址视为同一站点。
+    /// 一个保存目录只属于一个站点，目录已有其它站点的记录时返回配置错误
+    pub fn open(save_path: &Path, site: &str) -> Result<Self> {
+        let site = site_url(site);
+        let conn = Connection::open(Self::path_for(save_path))?;
+        // 常驻模式下多个搜索可能同时写入
+        conn.busy_timeout(Duration::from_secs(10))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS images (
+                 site           TEXT    NOT NULL,
+                 image_id       INTEGER NOT NULL,
+                 path           TEXT    NOT NULL,
+                 sha512         TEXT,
+                 size           INTEGER NOT NULL,
+                 representation TEXT,
+                 downloaded_at  TEXT    NOT NULL,
+                 PRIMARY KEY (site, image_id)
+             );
+             CREATE TABLE IF NOT EXISTS sync (
+                 site       TEXT    NOT NULL,
+                 query      TEXT    NOT NULL,
+                 filter_id  INTEGER NOT NULL,
+                 newest_id  INTEGER NOT NULL,
+                 updated_at TEXT    NOT NULL,
+                 PRIMARY KEY (site, query, filter_id)
+             );
+             CREATE TABLE IF NOT EXISTS scans (
+                 site       TEXT NOT NULL PRIMARY KEY,
+                 scanned_at TEXT NOT NULL
+             );",
+        )?;
+        // 旧记录可能以未规范的地址为键，归并到规范地址下
+        for table in ["images", "sync", "scans"] {
+            conn.execute(
+                &format!(
+                    "UPDATE OR IGNORE {table} SET site = ?1
+                     WHERE site != ?1 AND rtrim(site, '/') = rtrim(?1, '/')"
+                ),
+                params![site],
+            )?;
+            conn.execute(
+                &format!(
+                    "DELETE FROM {table} WHERE site != ?1 AND rtrim(site, '/') = rtrim(?1, '/')"
+                ),
+                params![site],
+            )?;
+        }
+
+        // 不同站点的 ID 互不相干，文件名也可能相同，不能共用目录
+        let other: Option<String> = conn
+            .query_row(
+                "SELECT site FROM images WHERE site != ?1
+                 UNION SELECT site FROM sync WHERE site != ?1
+                 UNION SELECT site FROM scans WHERE site != ?1
+                 LIMIT 1",
+                params![site],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(other) = other {
+            return Err(FerrumenaError::Config(format!(
+                "保存目录 {} 已用于站点 {}，{} 请使用其它目录",
+                save_path.display(),
+                other,
+                site
+            )));
+        }
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            site,
+        })
+    }
+
+    /// 记录所属的站点
+    pub fn site(&self) -> &str {
+        &self.site
+    }
+
+    /// 是否还没有为当前站点扫描过保存目录（如首次使用）
+    pub fn needs_scan(&self) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM scans WHERE site = ?1",
+            params![self.site],
+            |row| row.get(0),
+        )?;
+        Ok(count == 0)
+    }
+
+    /// 当前站点已下载的全部图片 ID
+    pub fn ids(&self) -> Result<HashSet<u32>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT image_id FROM images WHERE site = ?1")?;
+        let ids = stmt
+            .query_map(params![self.site], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<u32>>>()?;
+        Ok(ids)
+    }
+
+    /// 写入一条记录，同一图片已有记录时覆盖
+    pub fn record(&self, record: &DownloadRecord) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::insert(&conn, &self.site, record)?;
+        Ok(())
+    }
+
+    /// 用目录扫描的结果替换当前站点的全部记录，返回写入的条数
+    pub fn rebuild(&self, records: &[DownloadRecord]) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM images WHERE site = ?1", params![self.site])?;
+        for record in records {
+            Self::insert(&tx, &self.site, record)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO scans (site, scanned_at) VALUES (?1, ?2)",
+            params![self.site, Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(records.len())
+    }
+
+    /// 增量同步的位置：该搜索上次完整运行时见到的最大图片 ID
+    pub fn sync_position(&self, query: &str, filter_id: u32) -> Result<Option<u32>> {
+        let conn = self.conn.lock().unwrap();
+        let newest = conn
+            .query_row(
+                "SELECT newest_id FROM sync WHERE site = ?1 AND query = ?2 AND filter_id = ?3",
+                params![self.site, query, filter_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(newest)
+    }
+
+    /// 更新增量同步的位置
+    pub fn set_sync_position(&self, query: &str, filter_id: u32, newest_id: u32) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO sync (site, query, filter_id, newest_id, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                self.site,
+                query,
+                filter_id,
+                newest_id,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn insert(conn: &Connection, site: &str, record: &DownloadRecord) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO images
+                 (site, image_id, path, sha512, size, representation, downloaded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                site,
+                record.image_id,
+                record.path,
+                record.sha512,
+                record.size as i64,
+                record.representation,
+                record.downloaded_at.to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(image_id: u32) -> DownloadRecord {
+        DownloadRecord {
+            image_id,
+            path: format!("{}.png", image_id),
+            sha512: None,
+            size: 1,
+            representation: None,
+            downloaded_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn trailing_slash_does_not_split_site() {
+        let dir = std::env::temp_dir().join(format!("ferrumena-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // 模拟旧版本以未规范的地址写入的记录
+        {
+            let db = StateDb::open(&dir, "https://example.org").unwrap();
+            db.record(&record(1)).unwrap();
+            db.conn
+                .lock()
+                .unwrap()
+                .execute("UPDATE images SET site = 'https://example.org'", params![])
+                .unwrap();
+        }
+
+        let with_slash = StateDb::open(&dir, "https://example.org/").unwrap();
+        assert_eq!(with_slash.site(), "https://example.org/");
+        with_slash.record(&record(2)).unwrap();
+        drop(with_slash);
+
+        let without_slash = StateDb::open(&dir, "https://example.org").unwrap();
+        assert_eq!(without_slash.ids().unwrap(), HashSet::from([1, 2]));
+        drop(without_slash);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_belongs_to_one_site() {
+        let dir = std::env::temp_dir().join(format!("ferrumena-sites-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let first = StateDb::open(&dir, "https://example.org/").unwrap();
+        first.rebuild(&[]).unwrap();
+        drop(first);
+
+        assert!(matches!(
+            StateDb::open(&dir, "https://example.com/"),
+            Err(FerrumenaError::Config(_))
+        ));
+        assert!(StateDb::open(&dir, "https://example.org").is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,9 @@
 use crate::error::{FerrumenaError, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use scraper::Selector;
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub fn compact_url_for_log(url: &str) -> String {
     if let Some(scheme_pos) = url.find("://") {
@@ -14,6 +17,33 @@ pub fn compact_url_for_log(url: &str) -> String {
     url.to_string()
 }
 
+/// 解析带 s / m / h / d 后缀的时长，例如 `30s`、`10m`、`2h`，不带后缀时按秒计
+pub fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let value = value.trim();
+    let (num, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(pos) => value.split_at(pos),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("无法识别的时间单位: {}", unit)),
+    };
+    let num: u64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("无效的时长: {}", value))?;
+    if num == 0 {
+        return Err(format!("时长必须大于 0: {}", value));
+    }
+    let secs = num
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("时长过长: {}", value))?;
+    Ok(Duration::from_secs(secs))
+}
+
 pub fn parse_representations(uris_str: &str, id: u32) -> Result<HashMap<String, String>> {
     let uris_unescaped = uris_str.replace("&quot;", "\"");
     serde_json::from_str(&uris_unescaped)
@@ -29,6 +59,66 @@ pub fn pick_view_url(representations: &HashMap<String, String>) -> String {
         .unwrap_or_default()
 }
 
+/// 解析可选的 `data-*` 属性，缺失或格式不对时返回 None
+pub fn parse_attr<T: FromStr>(value: Option<&str>) -> Option<T> {
+    value.and_then(|v| v.trim().parse().ok())
+}
+
+/// 解析时间属性，带时区的 RFC 3339 之外也接受不带时区的 ISO 时间（视为 UTC）
+pub fn parse_timestamp(value: Option<&str>) -> Option<DateTime<Utc>> {
+    let value = value?.trim();
+    value.parse::<DateTime<Utc>>().ok().or_else(|| {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+            .ok()
+            .map(|dt| dt.and_utc())
+    })
+}
+
+/// 解析 `data-image-tags`（JSON 数组形式的标签 ID 列表）
+pub fn parse_tag_ids(value: &str) -> Vec<u32> {
+    serde_json::from_str(&value.replace("&quot;", "\"")).unwrap_or_default()
+}
+
+/// 拆分逗号分隔的标签列表
+pub fn split_tag_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 站点地址统一以 `/` 结尾，便于拼接路径和按站点分组
+pub fn site_url(url: &str) -> String {
+    format!("{}/", url.trim().trim_end_matches('/'))
+}
+
+/// 由文件扩展名推断 MIME 类型（HTML 后端拿不到 mime_type 时使用）
+pub fn mime_from_format(format: &str) -> Option<&'static str> {
+    match format.to_ascii_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "svg" => Some("image/svg+xml"),
+        "webp" => Some("image/webp"),
+        "webm" => Some("video/webm"),
+        "mp4" => Some("video/mp4"),
+        _ => None,
+    }
+}
+
+/// 从下载链接的路径中取出文件扩展名（小写），不是已知的媒体格式时返回 None
+///
+/// 非 full 的 representation 与原图格式不一定相同（SVG 的缩略图是 PNG，
+/// GIF 的是 WebM/MP4），保存和校验都应以实际下载的链接为准
+pub fn ext_from_url(url: &str) -> Option<String> {
+    let path = reqwest::Url::parse(url).ok()?.path().to_string();
+    let (_, ext) = path.rsplit('/').next()?.rsplit_once('.')?;
+    let ext = ext.to_ascii_lowercase();
+    mime_from_format(&ext).map(|_| ext)
+}
+
 pub fn extract_total_from_first_page(document: &scraper::Html) -> Result<u32> {
     let page_info_selector =
         Selector::parse("span.page__info strong").map_err(|_| FerrumenaError::DomParse {
@@ -49,3 +139,42 @@ pub fn extract_total_from_first_page(document: &scraper::Html) -> Result<u32> {
         .parse::<u32>()
         .map_err(|_| FerrumenaError::Logic(format!("无法解析总数信息: {}", total_text)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamp_accepts_naive_iso() {
+        let expected = "2024-03-05T12:34:56Z".parse::<DateTime<Utc>>().ok();
+        assert_eq!(parse_timestamp(Some("2024-03-05T12:34:56Z")), expected);
+        assert_eq!(parse_timestamp(Some("2024-03-05T12:34:56")), expected);
+        assert_eq!(parse_timestamp(Some(" 2024-03-05T12:34:56.000 ")), expected);
+        assert_eq!(parse_timestamp(Some("yesterday")), None);
+        assert_eq!(parse_timestamp(None), None);
+    }
+
+    #[test]
+    fn site_url_is_normalized() {
+        assert_eq!(
+            site_url("https://derpibooru.org"),
+            "https://derpibooru.org/"
+        );
+        assert_eq!(
+            site_url(" https://derpibooru.org// "),
+            "https://derpibooru.org/"
+        );
+    }
+
+    #[test]
+    fn parse_duration_units_and_overflow() {
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration(" 2H "), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("0m").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX)).is_err());
+    }
+}
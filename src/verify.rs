@@ -0,0 +1,209 @@
+use crate::api::models::ImageItem;
+use crate::error::{FerrumenaError, Result};
+use serde::{Deserialize, Serialize};
+
+/// 下载文件的校验结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    /// 与站点提供的 SHA-512 一致
+    Verified,
+    /// 站点没有提供可比对的哈希（HTML 后端、非 full 质量级别等）
+    Unverified,
+    /// 用户关闭了校验
+    Disabled,
+}
+
+impl std::fmt::Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Verification::Verified => write!(f, "已校验"),
+            Verification::Unverified => write!(f, "无可用哈希"),
+            Verification::Disabled => write!(f, "未校验"),
+        }
+    }
+}
+
+/// 判断文件格式所需的文件头字节数
+pub const SNIFF_LEN: usize = 64;
+
+/// 根据文件头判断实际格式，返回规范化的扩展名；无法识别时返回 None
+pub fn sniff_format(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("png");
+    }
+    if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("jpg");
+    }
+    if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        return Some("gif");
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return Some("webp");
+    }
+    if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some("webm");
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some("mp4");
+    }
+
+    // 文本类：去掉 BOM 和前导空白后看开头
+    let text = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let start = text.iter().position(|b| !b.is_ascii_whitespace())?;
+    let lower = text[start..].to_ascii_lowercase();
+    if lower.starts_with(b"<") {
+        // SVG 前面可能有 XML 声明、注释和 doctype，较长的注释会超出文件头，
+        // 因此只要是标记且看不到 HTML 的特征就视为 SVG
+        let contains = |pattern: &[u8]| lower.windows(pattern.len()).any(|w| w == pattern);
+        if contains(b"<!doctype html") || contains(b"<html") {
+            return Some("html");
+        }
+        if lower.starts_with(b"<svg") || lower.starts_with(b"<?xml") || lower.starts_with(b"<!") {
+            return Some("svg");
+        }
+    }
+    if lower.starts_with(b"{") || lower.starts_with(b"[") {
+        return Some("json");
+    }
+    None
+}
+
+/// 检查文件头是否与预期扩展名一致
+///
+/// 预期格式本身无法识别时放行；预期格式可识别但文件头对不上（包括无法识别）时报错
+pub fn check_content(id: u32, expected_ext: &str, head: &[u8]) -> Result<()> {
+    let expected = match expected_ext.to_ascii_lowercase().as_str() {
+        "jpeg" | "jpg" => "jpg",
+        "png" => "png",
+        "gif" => "gif",
+        "webp" => "webp",
+        "webm" => "webm",
+        "mp4" => "mp4",
+        "svg" => "svg",
+        _ => return Ok(()),
+    };
+
+    let actual = sniff_format(head);
+    if actual == Some(expected) {
+        return Ok(());
+    }
+    Err(FerrumenaError::ContentMismatch {
+        id,
+        expected: expected.to_string(),
+        actual: actual.unwrap_or("未知格式").to_string(),
+    })
+}
+
+/// 将下载结果与站点哈希比对
+///
+/// 站点的 `sha512_hash` 对应处理后的存储文件，`orig_sha512_hash` 对应原始上传，
+/// 两者都只描述完整文件，缩略图等其他 representation 无法校验
+pub fn check(image: &ImageItem, representation: &str, actual: &str) -> Result<Verification> {
+    if !matches!(representation, "full" | "view") {
+        return Ok(Verification::Unverified);
+    }
+
+    let expected: Vec<&str> = [&image.sha512_hash, &image.orig_sha512_hash]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .filter(|h| !h.is_empty())
+        .collect();
+
+    if expected.is_empty() {
+        return Ok(Verification::Unverified);
+    }
+    if expected.iter().any(|h| h.eq_ignore_ascii_case(actual)) {
+        return Ok(Verification::Verified);
+    }
+
+    Err(FerrumenaError::HashMismatch {
+        id: image.id,
+        expected: expected[0].to_string(),
+        actual: actual.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_formats() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some("png")),
+            (b"\xFF\xD8\xFF\xE0\0\x10JFIF", Some("jpg")),
+            (b"GIF87a\x01\0\x01\0", Some("gif")),
+            (b"GIF89a\x01\0\x01\0", Some("gif")),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", Some("webp")),
+            (b"\x1A\x45\xDF\xA3\x9F\x42\x86\x81", Some("webm")),
+            (b"\0\0\0\x20ftypisom\0\0\x02\0", Some("mp4")),
+            (b"<!DOCTYPE html><html><head>", Some("html")),
+            (b"\n  <HTML lang=\"en\">", Some("html")),
+            (
+                b"<?xml version=\"1.0\"?>\n<html xmlns=\"http://www.w3.org/1999/xhtml\">",
+                Some("html"),
+            ),
+            (b"<!-- cloudflare --><html>", Some("html")),
+            (b"<svg xmlns=\"http://www.w3.org/2000/svg\">", Some("svg")),
+            (
+                b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<svg>",
+                Some("svg"),
+            ),
+            (b"<!-- Created with Inkscape -->\n<svg>", Some("svg")),
+            (
+                b"<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\"",
+                Some("svg"),
+            ),
+            (b"\xEF\xBB\xBF  \r\n<svg>", Some("svg")),
+            (b"\xEF\xBB\xBF<!DOCTYPE html>", Some("html")),
+            (b"{\"error\":\"not found\"}", Some("json")),
+            (b"  [1, 2]", Some("json")),
+            (b"\x89PN", None),
+            (b"RIFF\x24\0\0\0WAVE", None),
+            (b"plain text", None),
+            (b"\xEF\xBB\xBF   ", None),
+            (b"", None),
+        ];
+        for (head, expected) in cases {
+            assert_eq!(
+                sniff_format(head),
+                *expected,
+                "{:?}",
+                String::from_utf8_lossy(head)
+            );
+        }
+    }
+
+    #[test]
+    fn checks_content_against_extension() {
+        let png = b"\x89PNG\r\n\x1a\n".as_slice();
+        let jpg = b"\xFF\xD8\xFF\xE0".as_slice();
+        let html = b"<!DOCTYPE html><title>502</title>".as_slice();
+        let svg = b"<?xml version=\"1.0\"?><svg>".as_slice();
+        let cases: &[(&str, &[u8], Option<&str>)] = &[
+            ("png", png, None),
+            ("PNG", png, None),
+            ("jpeg", jpg, None),
+            ("jpg", jpg, None),
+            ("svg", svg, None),
+            ("png", html, Some("html")),
+            ("jpg", png, Some("png")),
+            ("svg", html, Some("html")),
+            ("webm", b"garbage".as_slice(), Some("未知格式")),
+            // 无法识别的预期格式一律放行
+            ("avif", html, None),
+            ("", html, None),
+        ];
+        for (ext, head, mismatch) in cases {
+            match (check_content(1, ext, head), mismatch) {
+                (Ok(()), None) => {}
+                (Err(FerrumenaError::ContentMismatch { actual, .. }), Some(expected)) => {
+                    assert_eq!(actual, *expected, "{}", ext)
+                }
+                (result, _) => panic!("{} {:?}: {:?}", ext, String::from_utf8_lossy(head), result),
+            }
+        }
+    }
+}
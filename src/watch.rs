@@ -0,0 +1,193 @@
+use crate::api::client::PhilomenaClient;
+use crate::downloader::Downloader;
+use crate::error::{FerrumenaError, Result};
+use crate::events::{DownloadEvent, DownloadObserver};
+use crate::search::SearchRequest;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+pub use crate::utils::parse_duration;
+
+/// 命令行中的一个轮询搜索：`<间隔>=<搜索句>` 或单独的搜索句
+///
+/// 例如 `10m=artist:example`；不带间隔时使用默认间隔
+#[derive(Debug, Clone)]
+pub struct WatchQuery {
+    pub query: String,
+    pub interval: Option<Duration>,
+}
+
+impl FromStr for WatchQuery {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        // `=` 前面不是合法的时长时，整体视为搜索句
+        let (interval, query) = match value.split_once('=') {
+            Some((head, rest)) => match parse_duration(head) {
+                Ok(interval) => (Some(interval), rest),
+                Err(_) => (None, value),
+            },
+            None => (None, value),
+        };
+        if query.trim().is_empty() {
+            return Err(format!("搜索句不能为空: {}", value));
+        }
+        Ok(Self {
+            query: query.to_string(),
+            interval,
+        })
+    }
+}
+
+/// 常驻模式下轮询的一个搜索
+#[derive(Debug, Clone)]
+pub struct WatchJob {
+    pub search: SearchRequest,
+    /// 上一轮结束到下一轮开始之间的等待时间
+    pub interval: Duration,
+}
+
+/// 常驻运行，按各自的间隔反复执行每个搜索，只下载新上传的图片
+///
+/// 所有搜索共用同一个客户端（及其限速器），各自以增量同步方式运行；
+/// 单轮出错（网络中断、站点拦截等）时报告后等到下一轮继续，只有配置错误会结束运行。
+/// `shutdown` 取消后，等进行中的下载完成后返回
+pub async fn watch<O>(
+    client: Arc<PhilomenaClient>,
+    jobs: Vec<WatchJob>,
+    observer: O,
+    shutdown: CancellationToken,
+) -> Result<()>
+where
+    O: DownloadObserver + Clone + 'static,
+{
+    // 某个搜索因配置错误退出时，其余搜索也一起停止
+    let stop = shutdown.child_token();
+    let mut handles = Vec::new();
+    for job in jobs {
+        let client = Arc::clone(&client);
+        let observer = observer.clone();
+        let stop = stop.clone();
+        handles.push(tokio::spawn(async move {
+            let result = poll(client, &job, observer, &stop).await;
+            if result.is_err() {
+                stop.cancel();
+            }
+            result
+        }));
+    }
+
+    let mut first_error = None;
+    for handle in handles {
+        let result = handle
+            .await
+            .unwrap_or_else(|e| Err(FerrumenaError::Logic(format!("轮询任务异常退出: {}", e))));
+        if let Err(e) = result {
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+/// 反复执行一个搜索，直到取消或遇到配置错误
+async fn poll<O>(
+    client: Arc<PhilomenaClient>,
+    job: &WatchJob,
+    observer: O,
+    stop: &CancellationToken,
+) -> Result<()>
+where
+    O: DownloadObserver + Clone + 'static,
+{
+    let query = job.search.query.as_str();
+    // --rebuild-state 只在第一轮重建，之后每轮都沿用已有状态，否则每次都要重新扫描整个目录
+    let mut rebuild_state = client.config.rebuild_state;
+    while !stop.is_cancelled() {
+        observer.on_event(&DownloadEvent::PollStarted { query });
+        let result = async {
+            Downloader::builder(Arc::clone(&client), job.search.clone())
+                .sync(true)
+                .rebuild_state(rebuild_state)
+                .shutdown(stop.clone())
+                .observer(observer.clone())
+                .build()
+                .await?
+                .run()
+                .await
+        }
+        .await;
+        rebuild_state = false;
+
+        match result {
+            Ok(_) => {}
+            // 配置错误每一轮都会一样，继续轮询没有意义
+            Err(e @ FerrumenaError::Config(_)) => return Err(e),
+            Err(error) => observer.on_event(&DownloadEvent::PollFailed {
+                query,
+                error: &error,
+            }),
+        }
+
+        if stop.is_cancelled() {
+            break;
+        }
+        observer.on_event(&DownloadEvent::PollScheduled {
+            query,
+            delay: job.interval,
+        });
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            _ = tokio::time::sleep(job.interval) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str) -> (String, Option<Duration>) {
+        let q: WatchQuery = value.parse().unwrap();
+        (q.query, q.interval)
+    }
+
+    #[test]
+    fn interval_prefix() {
+        assert_eq!(
+            parse("10m=artist:example"),
+            ("artist:example".to_string(), Some(Duration::from_secs(600)))
+        );
+        assert_eq!(
+            parse("90=safe"),
+            ("safe".to_string(), Some(Duration::from_secs(90)))
+        );
+    }
+
+    #[test]
+    fn plain_query() {
+        assert_eq!(parse("safe, pony"), ("safe, pony".to_string(), None));
+    }
+
+    #[test]
+    fn equals_inside_query() {
+        // `=` 前面不是时长，整体视为搜索句
+        assert_eq!(parse("q=with=equals"), ("q=with=equals".to_string(), None));
+        assert_eq!(
+            parse("1h=score.gte:100 && q=x"),
+            (
+                "score.gte:100 && q=x".to_string(),
+                Some(Duration::from_secs(3600))
+            )
+        );
+    }
+
+    #[test]
+    fn empty_query_is_rejected() {
+        assert!("".parse::<WatchQuery>().is_err());
+        assert!("10m=".parse::<WatchQuery>().is_err());
+        assert!("10m=  ".parse::<WatchQuery>().is_err());
+    }
+}